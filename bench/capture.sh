@@ -0,0 +1,17 @@
+#!/bin/sh
+# Prints the screen of COMMAND once it has run for a few seconds in a 120x40 tmux window, styled as
+# `capture-pane -e` captures it, which is how the other captures here were taken:
+#
+#   bench/capture.sh htop > bench/htop.ans
+#
+# DELAY sets how many seconds to wait before capturing, 2 by default.
+
+set -eu
+
+socket="kak-popup-bench-$$"
+
+tmux -L "$socket" -f /dev/null new-session -d -x 120 -y 40 "$@"
+trap 'tmux -L "$socket" kill-server 2>/dev/null' EXIT
+
+sleep "${DELAY:-2}"
+tmux -L "$socket" capture-pane -p -e
@@ -1,6 +1,7 @@
-use std::ffi::OsString;
+use std::{ffi::OsString, path::PathBuf};
 
 use clap::{Args as SubcommandArgs, Parser, Subcommand, ValueEnum};
+use encoding_rs::Encoding;
 use strum::Display;
 
 #[derive(Clone, Copy, Default, Debug, Display, ValueEnum)]
@@ -15,8 +16,31 @@ pub enum OnErr {
   Ignore,
 }
 
+#[derive(Clone, Copy, Default, Debug, Display, ValueEnum)]
+#[strum(serialize_all = "snake_case")]
+pub enum Backend {
+  /// Run COMMAND in a tmux session, which allows detaching.
+  #[default]
+  Tmux,
+  /// Run COMMAND in a terminal emulated by kak-popup itself, without requiring tmux.
+  Pty,
+}
+
+#[derive(Clone, Copy, Default, Debug, Display, ValueEnum)]
+pub enum ColorDepth {
+  /// Any RGB colour.
+  #[default]
+  #[strum(serialize = "truecolor")]
+  #[value(name = "truecolor")]
+  Truecolor,
+  /// The 16 basic and bright colours.
+  #[strum(serialize = "16")]
+  #[value(name = "16")]
+  Colors16,
+}
+
 #[derive(SubcommandArgs)]
-pub struct Popup {
+pub struct Client {
   /// Daemonizes the process.
   #[arg(short, long)]
   pub daemonize: bool,
@@ -33,10 +57,6 @@ pub struct Popup {
   #[arg(long)]
   pub kak_client: String,
 
-  /// The kakoune script to execute on completion.
-  #[arg(long)]
-  pub kak_script: Option<String>,
-
   /// The height of the kakoune window.
   #[arg(long)]
   pub height: usize,
@@ -49,6 +69,55 @@ pub struct Popup {
   #[arg(long, default_value_t = 16)]
   pub padding: usize,
 
+  /// The shortest time between redraws in milliseconds, used right after a key is pressed.
+  #[arg(long, default_value_t = 10)]
+  pub min_refresh: u64,
+
+  /// The longest time between redraws in milliseconds, backed off to while COMMAND only produces output.
+  #[arg(long, default_value_t = 200)]
+  pub max_refresh: u64,
+
+  /// Keys bound to popup actions, as whitespace-separated ACTION=KEY pairs overriding the defaults:
+  /// quit=<c-space> detach=<a-space> scroll=<a-s> zoom=<a-z> literal=<a-v> paste=<a-p>. An empty KEY
+  /// unbinds the action. literal sends the next key to COMMAND as it is, even if it's bound, and paste
+  /// pastes the " register.
+  #[arg(long)]
+  pub keymap: Vec<String>,
+
+  /// Colours to draw in place of the terminal's, as whitespace-separated NAME=COLOUR pairs. NAME is
+  /// foreground, background, or one of the 16 basic and bright colours by index or kakoune name, such as
+  /// bright-black, and COLOUR is rgb:RRGGBB or #RRGGBB.
+  #[arg(long)]
+  pub palette: Vec<String>,
+
+  /// Draw foreground colours with the PopupBlack to PopupBrightWhite and PopupForeground faces, so that
+  /// they follow the colourscheme. Colours replaced by --palette are drawn as given.
+  #[arg(long)]
+  pub faces: bool,
+
+  /// The colours the terminal kakoune runs in can show. With 16, any other colour is drawn as the nearest of
+  /// the 16. There's no 256, as kakoune faces can only give the 16 by name and anything else as RGB.
+  #[arg(long, default_value_t)]
+  pub color_depth: ColorDepth,
+
+  /// The socket of the tmux server to run popups on, instead of one private to KAK_SESSION.
+  #[arg(long)]
+  pub tmux_socket: Option<PathBuf>,
+}
+
+fn encoding(label: &str) -> Result<&'static Encoding, String> {
+  Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("unknown encoding {label:?}"))
+}
+
+#[derive(SubcommandArgs)]
+pub struct Popup {
+  #[command(flatten)]
+  pub client: Client,
+
+  /// The kakoune script to execute on completion.
+  #[arg(long)]
+  pub kak_script: Option<String>,
+
   /// Input to pass as stdin to COMMAND.
   #[arg(long)]
   pub input: Option<OsString>,
@@ -61,6 +130,19 @@ pub struct Popup {
   #[arg(long)]
   pub title: Option<String>,
 
+  /// A unique name for the popup, used to refer to it once detached.
+  #[arg(long)]
+  pub name: Option<String>,
+
+  /// The terminal to run COMMAND in.
+  #[arg(long, default_value_t)]
+  pub backend: Backend,
+
+  /// The encoding COMMAND writes its output in, such as latin1 for programs predating UTF-8. Anything
+  /// that fails to decode is shown as U+FFFD. Only the pty backend supports encodings other than UTF-8.
+  #[arg(long, default_value = "utf-8", value_parser = encoding)]
+  pub encoding: &'static Encoding,
+
   /// The command to execute within the popup.
   pub command: String,
 
@@ -68,6 +150,68 @@ pub struct Popup {
   pub args: Vec<String>,
 }
 
+#[derive(SubcommandArgs)]
+pub struct Attach {
+  #[command(flatten)]
+  pub client: Client,
+
+  /// The name of the detached popup to re-open.
+  pub name: String,
+}
+
+#[derive(SubcommandArgs)]
+pub struct Yank {
+  /// The kakoune session to send the text to.
+  #[arg(long)]
+  pub kak_session: String,
+
+  /// The kakoune client to send the text to.
+  #[arg(long)]
+  pub kak_client: String,
+
+  /// The socket of the tmux server running the popup, instead of the one private to KAK_SESSION.
+  #[arg(long)]
+  pub tmux_socket: Option<PathBuf>,
+
+  /// Yank everything that has scrolled off the screen as well as the screen.
+  #[arg(long)]
+  pub history: bool,
+
+  /// The register to yank into.
+  #[arg(long, default_value = "dquote")]
+  pub register: String,
+
+  /// Yank into a scratch buffer named after the popup's title instead of a register.
+  #[arg(long, conflicts_with = "register")]
+  pub buffer: bool,
+
+  /// The name of the popup to yank from.
+  pub name: String,
+}
+
+#[derive(SubcommandArgs)]
+pub struct List {
+  /// Only list popups owned by this kakoune session.
+  #[arg(long)]
+  pub kak_session: Option<String>,
+
+  /// Only list popups on the tmux server at this socket.
+  #[arg(long)]
+  pub tmux_socket: Option<PathBuf>,
+}
+
+#[cfg(feature = "bench")]
+#[derive(SubcommandArgs)]
+pub struct Bench {
+  /// The number of times to render each capture.
+  #[arg(long, default_value_t = 1000)]
+  pub iterations: u32,
+
+  /// Screens captured with `tmux capture-pane -p -e`, such as those in bench/.
+  #[arg(required = true)]
+  pub captures: Vec<PathBuf>,
+}
+
 #[derive(Subcommand)]
 pub enum Command {
   /// Outputs kak script to be used prior to any call to `popup`.
@@ -75,6 +219,25 @@ pub enum Command {
 
   /// Starts a popup server instance.
   Popup(Popup),
+
+  /// Re-opens a detached popup.
+  Attach(Attach),
+
+  /// Sends the text of a named popup, without styling, to a kakoune register or scratch buffer.
+  Yank(Yank),
+
+  /// Prints running popups, one per line, as kakoune-quoted fields:
+  /// name, command, size (WIDTHxHEIGHT), kakoune session and kakoune client.
+  List(List),
+
+  /// Removes stale tmux sockets and the captured output of popups that exited while detached, and kills tmux servers
+  /// whose kakoune session has exited.
+  Gc,
+
+  /// Renders captured screens as popups would, reporting the size of each frame and how long it took.
+  #[cfg(feature = "bench")]
+  #[command(hide = true)]
+  Bench(Bench),
 }
 
 #[derive(Parser)]
@@ -0,0 +1,291 @@
+use std::sync::{mpsc::Sender, Arc};
+
+use anyhow::Result;
+use encoding_rs::{Encoding, UTF_8};
+use serde::Deserialize;
+
+use crate::{
+  args::Backend as BackendKind,
+  geometry::{Point, Size},
+  pty::Pty,
+  tmux::{Server, Tmux},
+};
+
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+pub struct DisplayInfo {
+  pub size: Size,
+  pub cursor: Point,
+  /// The number of lines that have scrolled off the top of the screen and can be scrolled back to.
+  pub history_size: usize,
+  /// The mouse reporting the program has asked for. Without any, the popup selects text instead.
+  pub mouse: Mouse,
+}
+
+/// The mouse modes a program enables with `CSI ? Pm h`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct Mouse {
+  pub tracking: MouseTracking,
+  pub encoding: MouseEncoding,
+}
+
+/// Which mouse events are reported.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseTracking {
+  #[default]
+  None,
+  /// Button presses, mode 9.
+  X10,
+  /// Presses and releases, mode 1000.
+  Normal,
+  /// Presses, releases and motion while a button is held, mode 1002.
+  Button,
+  /// Every event, including motion without any button held, mode 1003.
+  Any,
+}
+
+/// How mouse events are reported.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseEncoding {
+  /// `CSI M` followed by the button and coordinates as single bytes offset by 32.
+  #[default]
+  Default,
+  /// As the default, but with the coordinates as UTF-8 characters, mode 1005.
+  Utf8,
+  /// `CSI < button ; column ; line M`, or `m` for releases, mode 1006.
+  Sgr,
+  /// `CSI button ; column ; line M` with the button offset by 32, mode 1015.
+  Urxvt,
+}
+
+impl Mouse {
+  /// Encodes an xterm mouse report, where `button` includes the modifier and motion bits and the
+  /// coordinates count from 1. Returns `None` for events the program hasn't asked for, or that its
+  /// encoding can't express.
+  pub fn report(self, button: usize, column: usize, line: usize, release: bool) -> Option<Vec<u8>> {
+    let motion = button & 32 != 0;
+
+    let wanted = match self.tracking {
+      MouseTracking::None => false,
+      MouseTracking::X10 => !motion && !release,
+      MouseTracking::Normal => !motion,
+      MouseTracking::Button => !motion || button & 3 != 3,
+      MouseTracking::Any => true,
+    };
+
+    if !wanted {
+      return None;
+    }
+
+    // only SGR says which button was released, the others report the "release" button, 3
+    let legacy = if release { button | 3 } else { button };
+    let terminator = if release { 'm' } else { 'M' };
+
+    let report = match self.encoding {
+      MouseEncoding::Sgr => format!("\x1b[<{button};{column};{line}{terminator}").into_bytes(),
+      MouseEncoding::Urxvt => format!("\x1b[{};{column};{line}M", legacy + 32).into_bytes(),
+
+      MouseEncoding::Default => {
+        let byte = |value: usize| u8::try_from(value + 32).ok();
+
+        [b'\x1b', b'[', b'M', byte(legacy)?, byte(column)?, byte(line)?].to_vec()
+      }
+      MouseEncoding::Utf8 => {
+        let char = |value: usize| char::from_u32(u32::try_from(value + 32).ok()?);
+
+        ['\x1b', '[', 'M', char(legacy)?, char(column)?, char(line)?]
+          .iter()
+          .collect::<String>()
+          .into_bytes()
+      }
+    };
+
+    Some(report)
+  }
+}
+
+/// Something that may have changed what the popup shows.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+  /// The command wrote to the terminal, or exited.
+  Output,
+  /// Keys were sent to the terminal, or it was resized, so the user is waiting on a redraw.
+  Input,
+}
+
+/// The modifiers held with a key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers {
+  pub alt: bool,
+  pub ctrl: bool,
+  pub shift: bool,
+}
+
+impl Modifiers {
+  /// Kakoune's prefixes for alt, ctrl and shift, as in `<a-c-x>`.
+  pub const KAKOUNE: [&'static str; 3] = ["a-", "c-", "s-"];
+  /// Tmux's prefixes for alt, ctrl and shift, as in `M-C-x`.
+  pub const TMUX: [&'static str; 3] = ["M-", "C-", "S-"];
+
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Splits the key name `key` into the modifiers it starts with, written with `prefixes` in the order alt,
+  /// ctrl and shift, and the name of the key itself. A modifier has to be followed by a key, so a bare
+  /// prefix such as `a-` is the name of a key.
+  pub fn strip<'a>(mut key: &'a str, [alt, ctrl, shift]: [&str; 3]) -> (Self, &'a str) {
+    let mut modifiers = Self::new();
+
+    loop {
+      let stripped = [
+        (alt, &mut modifiers.alt),
+        (ctrl, &mut modifiers.ctrl),
+        (shift, &mut modifiers.shift),
+      ]
+      .into_iter()
+      .find_map(|(prefix, held)| Some((key.strip_prefix(prefix).filter(|rest| !rest.is_empty())?, held)));
+
+      let Some((rest, held)) = stripped else {
+        return (modifiers, key);
+      };
+
+      *held = true;
+      key = rest;
+    }
+  }
+}
+
+/// A key to send to the terminal, named as tmux's `send-keys` names them, or bytes sent as they are.
+pub enum Key {
+  Key(String),
+  Bytes(Vec<u8>),
+}
+
+/// A terminal that a popup's command runs in.
+pub trait Backend: Send + Sync {
+  fn display_info(&self) -> Result<DisplayInfo>;
+
+  /// The screen as it was `scroll` lines back into the history, one line per row, with SGR escape
+  /// sequences for styling.
+  fn capture_pane(&self, scroll: usize) -> Result<Vec<u8>>;
+
+  /// The whole history followed by the screen, in the same form as [`Backend::capture_pane`].
+  fn capture_history(&self) -> Result<Vec<u8>>;
+
+  /// Sends `keys` to the terminal in order, batched into as few writes as the terminal allows.
+  fn send_keys(&self, keys: Vec<Key>) -> Result<()>;
+
+  /// Types `text` into the terminal in one go, as a bracketed paste if the program has enabled it.
+  fn paste(&self, text: &str) -> Result<()>;
+
+  fn set_size(&self, size: Size) -> Result<()>;
+
+  fn kill(&self) -> Result<()>;
+
+  /// Registers `events` to be sent [`Event::Output`] whenever the screen may have changed, or the
+  /// terminal exited.
+  fn subscribe(&self, events: Sender<Event>);
+
+  /// The tmux session backing this terminal, which is required for detaching.
+  fn as_tmux(&self) -> Option<&Tmux> {
+    None
+  }
+}
+
+pub fn spawn(
+  kind: BackendKind,
+  server: Server,
+  name: Option<String>,
+  command: &[String],
+  size: Size,
+  encoding: &'static Encoding,
+) -> Result<Arc<dyn Backend>> {
+  Ok(match kind {
+    BackendKind::Tmux => {
+      anyhow::ensure!(encoding == UTF_8, "tmux popups only support UTF-8");
+
+      Arc::new(Tmux::new(server, name, command, size)?)
+    }
+    BackendKind::Pty => {
+      anyhow::ensure!(name.is_none(), "only tmux popups can be named");
+
+      Arc::new(Pty::new(command, size, encoding)?)
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Modifiers, Mouse, MouseEncoding, MouseTracking};
+
+  fn mouse(tracking: MouseTracking, encoding: MouseEncoding) -> Mouse {
+    Mouse { tracking, encoding }
+  }
+
+  #[test]
+  fn modifier_prefixes() {
+    let strip = |key| Modifiers::strip(key, Modifiers::TMUX);
+    let held = |alt, ctrl, shift| Modifiers { alt, ctrl, shift };
+
+    assert_eq!(strip("x"), (held(false, false, false), "x"));
+    assert_eq!(strip("C-M-x"), (held(true, true, false), "x"));
+    assert_eq!(strip("S-C-Up"), (held(false, true, true), "Up"));
+    assert_eq!(strip("M-€"), (held(true, false, false), "€"));
+
+    // a bare prefix is a key, even after other modifiers
+    assert_eq!(strip("M-"), (held(false, false, false), "M-"));
+    assert_eq!(strip("C-M-"), (held(false, true, false), "M-"));
+
+    assert_eq!(
+      Modifiers::strip("a-c-x", Modifiers::KAKOUNE),
+      (held(true, true, false), "x")
+    );
+  }
+
+  #[test]
+  fn tracking() {
+    // a left press, its release, motion with it held, and motion with no button held
+    let events = [(0, false), (0, true), (32, false), (35, false)];
+
+    for (tracking, wanted) in [
+      (MouseTracking::None, [false, false, false, false]),
+      (MouseTracking::X10, [true, false, false, false]),
+      (MouseTracking::Normal, [true, true, false, false]),
+      (MouseTracking::Button, [true, true, true, false]),
+      (MouseTracking::Any, [true, true, true, true]),
+    ] {
+      let mouse = mouse(tracking, MouseEncoding::Sgr);
+      let reported = events.map(|(button, release)| mouse.report(button, 1, 1, release).is_some());
+
+      assert_eq!(reported, wanted);
+    }
+  }
+
+  #[test]
+  fn encodings() {
+    let report =
+      |encoding, button, column, release| mouse(MouseTracking::Any, encoding).report(button, column, 6, release);
+
+    // only SGR says which button was released
+    assert_eq!(report(MouseEncoding::Sgr, 2, 5, false).unwrap(), b"\x1b[<2;5;6M");
+    assert_eq!(report(MouseEncoding::Sgr, 2, 5, true).unwrap(), b"\x1b[<2;5;6m");
+    assert_eq!(report(MouseEncoding::Default, 1, 5, false).unwrap(), b"\x1b[M!%&");
+    assert_eq!(report(MouseEncoding::Default, 1, 5, true).unwrap(), b"\x1b[M#%&");
+    assert_eq!(report(MouseEncoding::Utf8, 1, 5, true).unwrap(), b"\x1b[M#%&");
+    assert_eq!(report(MouseEncoding::Urxvt, 0, 5, false).unwrap(), b"\x1b[32;5;6M");
+    assert_eq!(report(MouseEncoding::Urxvt, 0, 5, true).unwrap(), b"\x1b[35;5;6M");
+  }
+
+  #[test]
+  fn large_coordinates() {
+    let report = |encoding, column| mouse(MouseTracking::Any, encoding).report(0, column, 1, false);
+
+    // the default encoding only has a byte for each coordinate
+    assert_eq!(report(MouseEncoding::Default, 223).unwrap(), b"\x1b[M \xff!");
+    assert_eq!(report(MouseEncoding::Default, 224), None);
+    assert_eq!(report(MouseEncoding::Utf8, 300).unwrap(), "\x1b[M Ō!".as_bytes());
+    assert_eq!(report(MouseEncoding::Sgr, 300).unwrap(), b"\x1b[<0;300;1M");
+  }
+}
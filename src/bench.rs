@@ -0,0 +1,55 @@
+use std::{
+  fs,
+  path::PathBuf,
+  time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+  backend::{DisplayInfo, Mouse},
+  buffer::{Buffer, Grid, Palette},
+  escape,
+  geometry::{Point, Size},
+};
+
+/// Renders each capture `iterations` times, printing the size of the frame sent to kakoune and how long
+/// rendering it took on average.
+pub fn bench(captures: &[PathBuf], iterations: u32) -> Result<()> {
+  println!("{:<24} {:>9} {:>12} {:>12}", "capture", "size", "bytes/frame", "render");
+
+  for path in captures {
+    let capture = fs::read(path).with_context(|| format!("reading {path:?}"))?;
+    let rows = Grid::new(&capture, None).rows;
+
+    // the screen was as wide as its widest line
+    let info = DisplayInfo {
+      size: Size {
+        height: rows.len(),
+        width: rows.iter().map(Vec::len).max().unwrap_or_default(),
+      },
+      cursor: Point { x: 0, y: 0 },
+      history_size: 0,
+      mouse: Mouse::default(),
+    };
+
+    let mut bytes = 0;
+    let mut elapsed = Duration::ZERO;
+
+    for _ in 0..iterations.max(1) {
+      let start = Instant::now();
+      let grid = Grid::new(&capture, Some(info.size.width));
+      let frame = escape::kak(Buffer::new(info.clone(), grid, None).markup(&Palette::default()));
+      elapsed += start.elapsed();
+
+      bytes = frame.len();
+    }
+
+    let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+    let size = format!("{}x{}", info.size.width, info.size.height);
+
+    println!("{name:<24} {size:>9} {bytes:>12} {:>12?}", elapsed / iterations.max(1));
+  }
+
+  Ok(())
+}
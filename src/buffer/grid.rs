@@ -0,0 +1,256 @@
+use unicode_width::UnicodeWidthChar;
+use vte::{Params, Perform};
+
+use super::style::Style;
+use crate::view::Selection;
+
+const ZERO_WIDTH_JOINER: char = '\u{200d}';
+const EMOJI_PRESENTATION: char = '\u{fe0f}';
+/// CAN, which aborts any escape sequence in progress.
+const CANCEL: u8 = 0x18;
+
+/// A column of the screen, with what's drawn in it and how.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Cell {
+  /// A base character followed by any that combine with it, or nothing in the column covered by the
+  /// right half of a wide character.
+  pub grapheme: String,
+  /// The number of columns the grapheme takes up.
+  pub width: usize,
+  /// The style in effect when the cell was drawn, including any set on earlier lines. Nothing is left
+  /// unset, so that cells which look the same compare equal however their styles were reached.
+  pub style: Style,
+}
+
+impl Cell {
+  fn blank() -> Self {
+    Self {
+      grapheme: " ".to_string(),
+      width: 1,
+      style: Style::reset(),
+    }
+  }
+
+  /// Whether the cell is the right half of the wide character before it.
+  pub fn is_continuation(&self) -> bool {
+    self.grapheme.is_empty()
+  }
+}
+
+/// A capture decoded into rows of cells, one per line, so that the escape sequences only have to be
+/// understood once. A row has a cell for every column, including both halves of wide characters.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Grid {
+  pub rows: Vec<Vec<Cell>>,
+}
+
+impl Grid {
+  /// Decodes `capture`, drawing any invalid UTF-8 as U+FFFD rather than failing. With a `width`, every row
+  /// is exactly that wide, dropping whatever overflows it and filling the rest with unstyled blanks.
+  pub fn new(capture: &[u8], width: Option<usize>) -> Self {
+    let mut decoder = Decoder {
+      rows: Vec::new(),
+      row: RowBuilder::new(width),
+      style: Style::reset(),
+    };
+
+    let mut parser = vte::Parser::new();
+
+    // like `str::lines`, a final line needn't end in a newline
+    let lines = capture.strip_suffix(b"\n").unwrap_or(capture);
+
+    for line in lines.split(|&byte| byte == b'\n').filter(|_| !capture.is_empty()) {
+      parser.advance(&mut decoder, line);
+
+      // a sequence or character cut short by the end of the line is abandoned rather than carried over
+      parser.advance(&mut decoder, &[CANCEL]);
+      decoder.finish_row();
+    }
+
+    Self { rows: decoder.rows }
+  }
+
+  /// The text of the grid, as it would be copied out of a terminal: trailing blanks are dropped from each
+  /// line, and blank lines from the end.
+  pub fn plain_text(&self) -> String {
+    let mut text = String::new();
+
+    for row in &self.rows {
+      let start = text.len();
+
+      text.extend(row.iter().map(|cell| cell.grapheme.as_str()));
+      text.truncate(start + text[start..].trim_end().len());
+      text.push('\n');
+    }
+
+    // the rest of the screen below the last output
+    text.truncate(text.trim_end_matches('\n').len());
+
+    if !text.is_empty() {
+      text.push('\n');
+    }
+
+    text
+  }
+
+  /// The text of the cells `selection` covers.
+  pub fn selected_text(&self, selection: &Selection) -> String {
+    let mut lines = Vec::new();
+
+    for (y, row) in self.rows.iter().enumerate() {
+      let columns = selection.columns(y);
+
+      if columns.is_empty() {
+        continue;
+      }
+
+      // a wide character is selected along with the column it starts in
+      let text: String = row
+        .iter()
+        .enumerate()
+        .filter(|(x, _)| columns.contains(x))
+        .map(|(_, cell)| cell.grapheme.as_str())
+        .collect();
+
+      lines.push(text.trim_end().to_string());
+    }
+
+    lines.join("\n")
+  }
+}
+
+/// Builds the rows of a grid from what [`vte::Parser`] makes of a capture, a line at a time. Only SGR
+/// sequences matter, as anything else that moves the cursor or changes modes is already reflected in the
+/// capture.
+struct Decoder {
+  rows: Vec<Vec<Cell>>,
+  row: RowBuilder,
+  /// The style in effect, which carries over from one line to the next.
+  style: Style,
+}
+
+impl Decoder {
+  fn finish_row(&mut self) {
+    let width = self.row.width;
+
+    let mut row = std::mem::replace(&mut self.row, RowBuilder::new(width));
+    row.flush(self.style);
+
+    let mut cells = row.cells;
+    if let Some(width) = width {
+      cells.resize(width, Cell::blank());
+    }
+
+    self.rows.push(cells);
+  }
+}
+
+impl Perform for Decoder {
+  fn print(&mut self, c: char) {
+    self.row.push(c, self.style);
+  }
+
+  fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+    if action != 'm' || !intermediates.is_empty() || ignore {
+      return;
+    }
+
+    self.row.flush(self.style);
+    self.style.merge(&Style::from_sgr(params));
+  }
+}
+
+/// A row being decoded, which groups characters into the cells they're drawn in.
+struct RowBuilder {
+  cells: Vec<Cell>,
+  width: Option<usize>,
+
+  /// The characters drawn in a single cell, a base character followed by any that combine with it.
+  cluster: String,
+  cluster_width: usize,
+}
+
+impl RowBuilder {
+  fn new(width: Option<usize>) -> Self {
+    Self {
+      cells: Vec::new(),
+      width,
+      cluster: String::new(),
+      cluster_width: 0,
+    }
+  }
+
+  fn push(&mut self, c: char, style: Style) {
+    let width = c.width().unwrap_or_default();
+    let joined = self.cluster.ends_with(ZERO_WIDTH_JOINER);
+
+    // combining marks, variation selectors and anything joined by a ZWJ belong to the preceding cell
+    if !self.cluster.is_empty() && (width == 0 || joined) {
+      self.cluster.push(c);
+
+      if c == EMOJI_PRESENTATION {
+        self.cluster_width = 2;
+      }
+
+      return;
+    }
+
+    self.flush(style);
+    self.cluster.push(c);
+    self.cluster_width = width;
+  }
+
+  /// Adds the pending cluster as a cell, unless it would overflow the row.
+  fn flush(&mut self, style: Style) {
+    if self.cluster.is_empty() {
+      return;
+    }
+
+    let grapheme = std::mem::take(&mut self.cluster);
+    let width = self.cluster_width;
+
+    // a mark left on its own by a style change still combines with the cell before it
+    if width == 0 {
+      if let Some(cell) = self.cells.iter_mut().rev().find(|cell| !cell.is_continuation()) {
+        cell.grapheme.push_str(&grapheme);
+      }
+
+      return;
+    }
+
+    if self.width.is_some_and(|max| self.cells.len() + width > max) {
+      return;
+    }
+
+    self.cells.push(Cell { grapheme, width, style });
+
+    for _ in 1..width {
+      self.cells.push(Cell {
+        grapheme: String::new(),
+        width: 0,
+        style,
+      });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Grid;
+
+  #[test]
+  fn equivalent_styles_compare_equal() {
+    let grid = |capture: &[u8]| Grid::new(capture, Some(8));
+
+    assert!(grid(b"\x1b[31mred\x1b[39m\n") == grid(b"\x1b[0m\x1b[31mred\x1b[0m\n"));
+    assert!(grid(b"plain") == grid(b"\x1b[1m\x1b[22mplain"));
+    assert!(grid(b"\x1b[31mred") != grid(b"\x1b[32mred"));
+  }
+
+  #[test]
+  fn plain_text() {
+    let grid = Grid::new(b"\x1b[1mone  \x1b[0m\ntwo\n\n\n", Some(8));
+
+    assert_eq!(grid.plain_text(), "one\ntwo\n");
+  }
+}
@@ -1,93 +1,88 @@
-mod ansi;
+mod grid;
 mod style;
 
-use std::str;
+pub use self::{
+  grid::Grid,
+  style::{Palette, Style},
+};
+use crate::{backend::DisplayInfo, view::Selection};
 
-use anyhow::Result;
-
-use self::{ansi::EscapeStack, style::Style};
-use crate::tmux::DisplayInfo;
+/// The face drawn over text selected with the mouse.
+const SELECTION_FACE: &str = "{PrimarySelection}";
 
 pub struct Buffer {
   info: DisplayInfo,
-  data: Vec<Vec<u8>>,
+  grid: Grid,
+  selection: Option<Selection>,
 }
 
-impl Buffer {
-  pub fn new(info: DisplayInfo, data: Vec<u8>) -> Self {
-    let mut lines = Vec::new();
-    let mut line = Vec::new();
-
-    for byte in data {
-      if byte == b'\n' {
-        lines.push(line);
-        line = Vec::new();
-      } else {
-        line.push(byte);
-      }
-    }
+/// What a cell is drawn with, which is its own style unless it's under the cursor or selected.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Face {
+  Cursor,
+  Selection,
+  Style(Style),
+}
 
-    if !line.is_empty() {
-      lines.push(line);
+impl Face {
+  fn markup(self, palette: &Palette) -> String {
+    match self {
+      Self::Cursor => "{PrimaryCursor}".to_string(),
+      Self::Selection => SELECTION_FACE.to_string(),
+      Self::Style(style) => style.markup(palette),
     }
+  }
+}
 
-    Self { info, data: lines }
+impl Buffer {
+  /// Draws `grid`, which is expected to have been decoded at the width of the pane in `info`.
+  pub fn new(info: DisplayInfo, grid: Grid, selection: Option<Selection>) -> Self {
+    Self { info, grid, selection }
   }
 
-  pub fn markup(self) -> Result<String> {
+  /// Renders the screen as kakoune markup, with lines exactly the width of the pane and the cursor drawn
+  /// on the right cell. A face is only given where the way cells look changes, which keeps frames small.
+  pub fn markup(self, palette: &Palette) -> String {
     let mut markup = String::new();
-    let mut esc = EscapeStack::new();
-    let mut style: Style = Style::default();
-
-    for (y, line) in self.data.into_iter().enumerate() {
-      let mut x: usize = 0;
-      let mut fill = false;
-      let mut chars = str::from_utf8(&line)?.chars();
-
-      while x < self.info.size.width {
-        let c = if fill {
-          ' '
-        } else if let Some(c) = chars.next() {
-          c
-        } else {
-          markup.push_str(&Style::default().markup());
-          fill = true;
-          ' '
-        };
+    let mut face = None;
+    // the info box starts out unstyled, so nothing is needed until a cell is styled
+    let mut face_markup = Style::default().markup(&Palette::default());
 
-        let (skip, new_style) = esc.skip(c);
-        if let Some(new_style) = new_style {
-          style.merge(&new_style);
-          markup.push_str(&style.markup());
-        }
-        if skip {
+    for (y, row) in self.grid.rows.iter().enumerate() {
+      let cursor = (y == self.info.cursor.y).then_some(self.info.cursor.x);
+      let selected = self.selection.map_or(0..0, |selection| selection.columns(y));
+
+      for (x, cell) in row.iter().enumerate() {
+        if cell.is_continuation() {
           continue;
         }
 
-        let at_cursor = x == self.info.cursor.x && y == self.info.cursor.y;
+        let next = if cursor.is_some_and(|cursor| (x..x + cell.width).contains(&cursor)) {
+          Face::Cursor
+        } else if selected.contains(&x) {
+          Face::Selection
+        } else {
+          Face::Style(cell.style)
+        };
 
-        if at_cursor {
-          markup.push_str("{PrimaryCursor}");
-        }
+        // styles that differ only in what they leave unset look the same, and are drawn with one face
+        if face != Some(next) {
+          face = Some(next);
 
-        match c {
-          '{' => markup.push_str("\\{"),
-          '\\' => markup.push_str("\\\\"),
-          c => markup.push(c),
+          let next_markup = next.markup(palette);
+          if next_markup != face_markup {
+            markup.push_str(&next_markup);
+            face_markup = next_markup;
+          }
         }
 
-        if at_cursor && fill {
-          markup.push_str(&Style::default().markup());
-        } else if at_cursor {
-          markup.push_str(&style.markup());
+        for c in cell.grapheme.chars() {
+          match c {
+            '{' => markup.push_str("\\{"),
+            '\\' => markup.push_str("\\\\"),
+            c => markup.push(c),
+          }
         }
-
-        x += 1;
-      }
-
-      // continue any styling preceding the inserted spaces
-      if fill {
-        markup.push_str(&style.markup());
       }
 
       markup.push('\n');
@@ -96,6 +91,88 @@ impl Buffer {
     // don't include last newline
     markup.pop();
 
-    Ok(markup)
+    markup
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Buffer, Grid, Palette};
+  use crate::{
+    backend::{DisplayInfo, Mouse},
+    geometry::{Point, Size},
+  };
+
+  /// The markup for `capture` on a screen `width` columns wide, with the cursor in the given column of the
+  /// first line, or else out of sight.
+  fn markup(capture: &[u8], width: usize, cursor: Option<usize>) -> String {
+    let info = DisplayInfo {
+      size: Size { height: 1, width },
+      cursor: cursor.map_or(Point { x: 0, y: usize::MAX }, |x| Point { x, y: 0 }),
+      history_size: 0,
+      mouse: Mouse::default(),
+    };
+
+    Buffer::new(info, Grid::new(capture, Some(width)), None).markup(&Palette::default())
+  }
+
+  #[test]
+  fn invalid_utf8() {
+    assert_eq!(markup(b"a\xc3(b", 5, None), "a\u{fffd}(b ");
+    assert_eq!(markup(b"\xff", 2, None), "\u{fffd} ");
+    assert_eq!(markup(b"\xe2\x82", 2, None), "\u{fffd} ");
+  }
+
+  #[test]
+  fn truncated_escapes() {
+    assert_eq!(markup(b"ok\x1b", 3, None), "ok ");
+    assert_eq!(markup(b"ok\x1b[3", 3, None), "ok ");
+    assert_eq!(markup(b"ok\x1b[38;2;1", 3, None), "ok ");
+    assert_eq!(markup(b"ok\x1b]0;title", 3, None), "ok ");
+    assert_eq!(markup(b"\x1b[31mok\x1b[", 3, None), "{red@Default}ok{@Default} ");
+
+    // cut short at the end of a line, rather than swallowing the next
+    assert_eq!(markup(b"a\x1b]0;title\nb", 1, None), "a\nb");
+    assert_eq!(markup(b"a\x1b[38;2\nb", 1, None), "a\nb");
+    assert_eq!(markup(b"a\xe2\x82\nb", 2, None), "a\u{fffd}\nb ");
+  }
+
+  #[test]
+  fn wide_characters() {
+    // padded to the width in columns, not characters
+    assert_eq!(markup("本日".as_bytes(), 6, None), "本日  ");
+    // one that doesn't fit in the last column is dropped
+    assert_eq!(markup("abc本".as_bytes(), 4, None), "abc ");
+    assert_eq!(markup("abc本d".as_bytes(), 5, None), "abc本");
+  }
+
+  #[test]
+  fn cursor_on_wide_characters() {
+    assert_eq!(markup("a本b".as_bytes(), 4, Some(1)), "a{PrimaryCursor}本{@Default}b");
+    // on the right half
+    assert_eq!(markup("a本b".as_bytes(), 4, Some(2)), "a{PrimaryCursor}本{@Default}b");
+    assert_eq!(markup("a本b".as_bytes(), 4, Some(3)), "a本{PrimaryCursor}b");
+  }
+
+  #[test]
+  fn clusters() {
+    // a combining mark stays in the cell of the character it marks
+    assert_eq!(markup("e\u{301}x".as_bytes(), 3, None), "e\u{301}x ");
+    assert_eq!(
+      markup("e\u{301}x".as_bytes(), 3, Some(1)),
+      "e\u{301}{PrimaryCursor}x{@Default} "
+    );
+
+    // joined emoji, and those made wide by a variation selector, take two cells
+    let emoji = "👩\u{200d}💻";
+    assert_eq!(markup(format!("{emoji}x").as_bytes(), 4, None), format!("{emoji}x "));
+    assert_eq!(
+      markup(format!("{emoji}x").as_bytes(), 4, Some(2)),
+      format!("{emoji}{{PrimaryCursor}}x{{@Default}} ")
+    );
+    assert_eq!(
+      markup("❤\u{fe0f}x".as_bytes(), 4, Some(2)),
+      "❤\u{fe0f}{PrimaryCursor}x{@Default} "
+    );
   }
 }
@@ -1,6 +1,30 @@
 use anyhow::Result;
 
-#[derive(Clone, Copy, Debug)]
+use vte::Params;
+
+use crate::args::ColorDepth;
+
+/// The colours xterm uses by default for the 16 basic and bright colours.
+const XTERM_BASE: [(u8, u8, u8); 16] = [
+  (0x00, 0x00, 0x00),
+  (0xcd, 0x00, 0x00),
+  (0x00, 0xcd, 0x00),
+  (0xcd, 0xcd, 0x00),
+  (0x00, 0x00, 0xee),
+  (0xcd, 0x00, 0xcd),
+  (0x00, 0xcd, 0xcd),
+  (0xe5, 0xe5, 0xe5),
+  (0x7f, 0x7f, 0x7f),
+  (0xff, 0x00, 0x00),
+  (0x00, 0xff, 0x00),
+  (0xff, 0xff, 0x00),
+  (0x5c, 0x5c, 0xff),
+  (0xff, 0x00, 0xff),
+  (0x00, 0xff, 0xff),
+  (0xff, 0xff, 0xff),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Color {
   Black,
   Red,
@@ -26,28 +50,134 @@ pub enum Color {
 }
 
 impl Color {
+  /// One of the 16 colours of the basic and bright ranges.
+  pub fn from_ansi(index: u8) -> Self {
+    match index {
+      0 => Self::Black,
+      1 => Self::Red,
+      2 => Self::Green,
+      3 => Self::Yellow,
+      4 => Self::Blue,
+      5 => Self::Magenta,
+      6 => Self::Cyan,
+      7 => Self::White,
+
+      8 => Self::BrightBlack,
+      9 => Self::BrightRed,
+      10 => Self::BrightGreen,
+      11 => Self::BrightYellow,
+      12 => Self::BrightBlue,
+      13 => Self::BrightMagenta,
+      14 => Self::BrightCyan,
+      _ => Self::BrightWhite,
+    }
+  }
+
   pub fn from_256(val: u8) -> Self {
     match val {
-      0..=7 => Color::from(val + 30),
-
-      8..=15 => Color::from(val + 82),
+      0..=15 => Color::from_ansi(val),
 
+      // xterm's 6x6x6 colour cube, whose levels are 95 and then steps of 40 above black
       16..=231 => {
-        let r = ((val - 16) / 36) * 51;
-        let g = ((val - 16) % 36 / 6) * 51;
-        let b = ((val - 16) % 6) * 51;
+        let level = |n: u8| if n == 0 { 0 } else { 55 + 40 * n };
+        let n = val - 16;
 
-        Color::Rgb(r, g, b)
+        Color::Rgb(level(n / 36), level(n % 36 / 6), level(n % 6))
       }
 
       232..=255 => {
-        let gray = (val - 232) * 10;
+        let gray = 8 + (val - 232) * 10;
 
         Color::Rgb(gray, gray, gray)
       }
     }
   }
 
+  /// Parses `rgb:RRGGBB`, as kakoune writes colours, or `#RRGGBB`.
+  fn parse_rgb(color: &str) -> Result<Self> {
+    let hex = color
+      .strip_prefix("rgb:")
+      .or_else(|| color.strip_prefix('#'))
+      .filter(|hex| hex.len() == 6 && hex.is_ascii())
+      .ok_or(anyhow::anyhow!(
+        "invalid colour {color:?}, expected rgb:RRGGBB or #RRGGBB"
+      ))?;
+
+    let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+
+    Ok(Self::Rgb(component(0)?, component(2)?, component(4)?))
+  }
+
+  /// The face `rc/popup.kak` declares for this colour, such as `PopupBrightBlack`, which for the default
+  /// colour is `PopupForeground`.
+  fn face(self) -> Option<String> {
+    let name = match self {
+      Self::Default => "foreground".to_string(),
+      Self::Rgb(..) => return None,
+      color => color.markup(),
+    };
+
+    let name: String = name
+      .split('-')
+      .flat_map(|word| {
+        let mut chars = word.chars();
+        chars.next().map(|c| c.to_ascii_uppercase()).into_iter().chain(chars)
+      })
+      .collect();
+
+    Some(format!("Popup{name}"))
+  }
+
+  /// The index of one of the 16 basic and bright colours.
+  fn ansi_index(self) -> Option<usize> {
+    (0..16).position(|index| Self::from_ansi(index) == self)
+  }
+
+  /// The SGR parameters setting this colour, using `extended` (38, 48 or 58) for RGB colours and for the 16
+  /// colours when `basic` doesn't give the codes of the basic and bright ranges. None for the default
+  /// colour, which a reset already sets.
+  fn sgr(self, extended: usize, basic: Option<(usize, usize)>) -> Option<String> {
+    let index = match self {
+      Self::Default => return None,
+      Self::Rgb(r, g, b) => return Some(format!("{extended};2;{r};{g};{b}")),
+      color => color.ansi_index()?,
+    };
+
+    Some(match basic {
+      Some((normal, _)) if index < 8 => (normal + index).to_string(),
+      Some((_, bright)) => (bright + index - 8).to_string(),
+      None => format!("{extended};5;{index}"),
+    })
+  }
+
+  /// Parses the colour following 38, 48 or 58, given either as its own subparameters (`38:2::r:g:b`)
+  /// or as the parameters after it (`38;2;r;g;b`), which are consumed from `rest`.
+  fn extended<'a>(subparams: &[u16], rest: &mut impl Iterator<Item = &'a [u16]>) -> Option<Self> {
+    let byte = |value: u16| u8::try_from(value).ok();
+
+    match subparams {
+      [] => {
+        let mut next = || rest.next().and_then(|param| param.first().copied());
+
+        match next()? {
+          5 => Some(Self::from_256(byte(next()?)?)),
+          2 => {
+            // always consumed, so that a bad component doesn't misread the rest of the sequence
+            let (r, g, b) = (next(), next(), next());
+            Some(Self::Rgb(byte(r?)?, byte(g?)?, byte(b?)?))
+          }
+          _ => None,
+        }
+      }
+
+      [5, index, ..] => Some(Self::from_256(byte(*index)?)),
+      // the colour space id is optional, and usually left empty
+      [2, _, r, g, b, ..] | [2, r, g, b] => Some(Self::Rgb(byte(*r)?, byte(*g)?, byte(*b)?)),
+
+      _ => None,
+    }
+  }
+
   pub fn markup(&self) -> String {
     match self {
       Self::Black => "black".to_string(),
@@ -68,7 +198,6 @@ impl Color {
       Self::BrightCyan => "bright-cyan".to_string(),
       Self::BrightWhite => "bright-white".to_string(),
 
-      // TODO: need to convert to padded hex
       Self::Rgb(r, g, b) => format!("rgb:{r:02X}{g:02X}{b:02X}"),
 
       Self::Default => "default".to_string(),
@@ -76,51 +205,191 @@ impl Color {
   }
 }
 
-impl From<u8> for Color {
-  fn from(param: u8) -> Self {
-    match param {
-      30 => Self::Black,
-      31 => Self::Red,
-      32 => Self::Green,
-      33 => Self::Yellow,
-      34 => Self::Blue,
-      35 => Self::Magenta,
-      36 => Self::Cyan,
-      37 => Self::White,
-
-      39 => Self::Default,
-
-      90 => Self::BrightBlack,
-      91 => Self::BrightRed,
-      92 => Self::BrightGreen,
-      93 => Self::BrightYellow,
-      94 => Self::BrightBlue,
-      95 => Self::BrightMagenta,
-      96 => Self::BrightCyan,
-      97 => Self::BrightWhite,
-
-      param => panic!("unexpected code: {param}"),
+/// Colours drawn in place of the 16 basic and bright colours, and of the default foreground and
+/// background, each a [`Color::Rgb`]. Colours without one are left to the kakoune client's terminal, or
+/// with `faces`, drawn with the `Popup*` faces wherever faces can be used.
+#[derive(Clone, Default)]
+pub struct Palette {
+  base: [Option<Color>; 16],
+  foreground: Option<Color>,
+  background: Option<Color>,
+  /// Draws the foreground with faces such as `PopupRed`, so that it follows the colourscheme. There's only
+  /// the one base face, so backgrounds other than the default are still drawn with colours.
+  pub faces: bool,
+  /// The colours the kakoune client's terminal can show, which any others are brought down to.
+  pub depth: ColorDepth,
+}
+
+impl Palette {
+  /// Applies whitespace-separated `name=colour` pairs. A name is `foreground`, `background`, or one of the
+  /// 16 colours by index or as kakoune names it, such as `bright-black`, and a colour is `rgb:RRGGBB` or
+  /// `#RRGGBB`.
+  pub fn set(&mut self, pairs: &str) -> Result<()> {
+    for pair in pairs.split_whitespace() {
+      let (name, color) = pair
+        .split_once('=')
+        .ok_or(anyhow::anyhow!("invalid palette entry {pair:?}, expected NAME=COLOUR"))?;
+      let color = Color::parse_rgb(color)?;
+
+      let entry = match name {
+        "foreground" => &mut self.foreground,
+        "background" => &mut self.background,
+
+        name => {
+          let index = match name.parse::<usize>() {
+            Ok(index @ 0..=15) => index,
+            _ => (0..16)
+              .position(|index| Color::from_ansi(index).markup() == name)
+              .ok_or(anyhow::anyhow!("unknown palette colour {name:?}"))?,
+          };
+
+          &mut self.base[index]
+        }
+      };
+
+      *entry = Some(color);
+    }
+
+    Ok(())
+  }
+
+  /// The colour drawn for `color`, which is its replacement if it's one of the 16 colours and has one,
+  /// brought down to the colour depth.
+  fn resolve(&self, color: Color) -> Color {
+    self.quantise(color.ansi_index().and_then(|index| self.base[index]).unwrap_or(color))
+  }
+
+  /// The colour nearest to `color` that the colour depth allows. With 16 colours, the nearest is judged by
+  /// the palette's replacements, or by xterm's colours for those without one.
+  fn quantise(&self, color: Color) -> Color {
+    let Color::Rgb(r, g, b) = color else {
+      return color;
+    };
+
+    if let ColorDepth::Truecolor = self.depth {
+      return color;
+    }
+
+    (0..16)
+      .map(Color::from_ansi)
+      .min_by_key(|candidate| distance((r, g, b), self.rgb(*candidate)))
+      .unwrap_or(color)
+  }
+
+  /// The RGB value of `color` as far as the palette knows it, which for the 16 colours is their
+  /// replacement or else xterm's colour.
+  fn rgb(&self, color: Color) -> (u8, u8, u8) {
+    match (color, color.ansi_index()) {
+      (Color::Rgb(r, g, b), _) => (r, g, b),
+      (_, Some(index)) => match self.base[index] {
+        Some(Color::Rgb(r, g, b)) => (r, g, b),
+        _ => XTERM_BASE[index],
+      },
+      // the default colour isn't a candidate
+      (_, None) => (0, 0, 0),
+    }
+  }
+}
+
+/// The underline styles of `4:n`, where dotted and dashed are drawn as plain underlines as kakoune lacks
+/// them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Underline {
+  None,
+  Single,
+  Double,
+  Curly,
+  Dotted,
+  Dashed,
+}
+
+impl Underline {
+  fn from_style(style: Option<u16>) -> Self {
+    match style {
+      Some(0) => Self::None,
+      Some(2) => Self::Double,
+      Some(3) => Self::Curly,
+      Some(4) => Self::Dotted,
+      Some(5) => Self::Dashed,
+      _ => Self::Single,
+    }
+  }
+
+  fn sgr(self) -> Option<&'static str> {
+    match self {
+      Self::None => None,
+      Self::Single => Some("4"),
+      Self::Double => Some("4:2"),
+      Self::Curly => Some("4:3"),
+      Self::Dotted => Some("4:4"),
+      Self::Dashed => Some("4:5"),
+    }
+  }
+
+  fn attribute(self) -> Option<char> {
+    match self {
+      Self::None => None,
+      Self::Single | Self::Dotted | Self::Dashed => Some('u'),
+      Self::Double => Some('U'),
+      Self::Curly => Some('c'),
     }
   }
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct Style {
   foreground: Option<Color>,
   background: Option<Color>,
+  underline_color: Option<Color>,
   bold: Option<bool>,
   dim: Option<bool>,
   italic: Option<bool>,
-  underline: Option<bool>,
+  underline: Option<Underline>,
   blink: Option<bool>,
   reverse: Option<bool>,
   strike: Option<bool>,
+  /// Parsed so that it doesn't affect anything else, but not drawn as kakoune has no overline.
+  overline: Option<bool>,
 }
 
 impl Style {
-  pub fn markup(&self) -> String {
-    let foreground = self.foreground.as_ref().map(Color::markup).unwrap_or_default();
-    let background = self.background.as_ref().map(Color::markup).unwrap_or_default();
+  /// The shortest face giving this style in `palette`: default colours are left empty unless the palette
+  /// replaces them, and trailing empty colours and attributes are left out. With the palette's faces, the
+  /// foreground comes from the base face instead, unless the palette replaces it.
+  pub fn markup(&self, palette: &Palette) -> String {
+    let color = |color: Option<Color>, default: Option<Color>| match color.unwrap_or(Color::Default) {
+      Color::Default => default
+        .map(|color| palette.quantise(color).markup())
+        .unwrap_or_default(),
+      color => palette.resolve(color).markup(),
+    };
+
+    // brought down first, so that a colour drawn as one of the 16 is given its face
+    let foreground = palette.quantise(self.foreground.unwrap_or(Color::Default));
+
+    // colours the palette replaces are drawn as given, even with faces
+    let face = match foreground {
+      Color::Default if palette.foreground.is_some() => None,
+      color if color.ansi_index().is_some_and(|index| palette.base[index].is_some()) => None,
+      color => color.face().filter(|_| palette.faces),
+    };
+
+    let (foreground, base) = match face {
+      Some(face) => (String::new(), face),
+      None => {
+        let base = if palette.faces { "PopupForeground" } else { "Default" };
+        (color(Some(foreground), palette.foreground), base.to_string())
+      }
+    };
+
+    let mut colors = [
+      foreground,
+      color(self.background, palette.background),
+      // an underline without a colour of its own takes the foreground's
+      color(self.underline_color, None),
+    ]
+    .join(",");
+    colors.truncate(colors.trim_end_matches(',').len());
 
     let mut attributes = String::new();
 
@@ -133,8 +402,8 @@ impl Style {
     if self.italic.unwrap_or_default() {
       attributes.push('i');
     };
-    if self.underline.unwrap_or_default() {
-      attributes.push('u');
+    if let Some(underline) = self.underline.and_then(Underline::attribute) {
+      attributes.push(underline);
     };
     if self.blink.unwrap_or_default() {
       attributes.push('B');
@@ -146,13 +415,18 @@ impl Style {
       attributes.push('s');
     };
 
-    format!("{{{foreground},{background}+{attributes}@Default}}")
+    if attributes.is_empty() {
+      format!("{{{colors}@{base}}}")
+    } else {
+      format!("{{{colors}+{attributes}@{base}}}")
+    }
   }
 
   pub fn merge(&mut self, other: &Self) {
     *self = Self {
       foreground: other.foreground.or(self.foreground),
       background: other.background.or(self.background),
+      underline_color: other.underline_color.or(self.underline_color),
       bold: other.bold.or(self.bold),
       dim: other.dim.or(self.dim),
       italic: other.italic.or(self.italic),
@@ -160,6 +434,7 @@ impl Style {
       blink: other.blink.or(self.blink),
       reverse: other.reverse.or(self.reverse),
       strike: other.strike.or(self.strike),
+      overline: other.overline.or(self.overline),
     }
   }
 
@@ -167,102 +442,292 @@ impl Style {
     Self {
       foreground: Some(Color::Default),
       background: Some(Color::Default),
+      underline_color: Some(Color::Default),
       bold: Some(false),
       dim: Some(false),
       italic: Some(false),
-      underline: Some(false),
+      underline: Some(Underline::None),
       blink: Some(false),
       reverse: Some(false),
       strike: Some(false),
+      overline: Some(false),
     }
   }
 
-  pub fn try_from(chars: &[char]) -> Result<Self> {
-    let mut params = Vec::<u8>::new();
-    let mut param = String::new();
-
-    // first two characters are ESC [
-    for c in &chars[2..] {
-      match c {
-        ';' => {
-          params.push(param.parse()?);
-          param.clear();
-        }
-        'm' => {
-          params.push(param.parse()?);
-          break;
-        }
-        _ => param.push(*c),
-      }
+  /// The style of cells erased while this one is in effect, which keeps only its background, as in xterm.
+  pub fn erased(&self) -> Self {
+    Self {
+      background: self.background,
+      ..Self::reset()
     }
+  }
 
+  /// The SGR sequence that sets exactly this style, starting from a reset.
+  pub fn sgr(&self) -> String {
+    let mut params = vec!["0".to_string()];
+
+    let attributes = [
+      (self.bold, "1"),
+      (self.dim, "2"),
+      (self.italic, "3"),
+      (self.blink, "5"),
+      (self.reverse, "7"),
+      (self.strike, "9"),
+      (self.overline, "53"),
+    ];
+    let attributes = attributes.into_iter().filter(|(set, _)| *set == Some(true));
+
+    params.extend(attributes.map(|(_, code)| code.to_string()));
+    params.extend(self.underline.and_then(Underline::sgr).map(String::from));
+
+    let colors = [
+      self.foreground.and_then(|color| color.sgr(38, Some((30, 90)))),
+      self.background.and_then(|color| color.sgr(48, Some((40, 100)))),
+      self.underline_color.and_then(|color| color.sgr(58, None)),
+    ];
+    params.extend(colors.into_iter().flatten());
+
+    format!("\x1b[{}m", params.join(";"))
+  }
+
+  /// Builds the style set by the parameters of an SGR sequence, `ESC [ ... m`. Unknown parameters are
+  /// skipped, and colours that are truncated or out of range are left unchanged.
+  pub fn from_sgr(params: &Params) -> Self {
+    let mut params = params.iter();
     let mut style = Self::default();
 
-    let mut i = 0;
-    while i < params.len() {
-      let param = params[i];
+    while let Some(param) = params.next() {
+      // an omitted parameter is 0, so `ESC [ m` and `ESC [ ; 1 m` both start with a reset
+      let (code, subparams) = param
+        .split_first()
+        .map_or((0, &[][..]), |(code, subparams)| (*code, subparams));
 
-      match param {
+      match code {
         0 => style = Self::reset(),
 
         1 => style.bold = Some(true),
         2 => style.dim = Some(true),
         3 => style.italic = Some(true),
-        4 => style.underline = Some(true),
-        5 => style.blink = Some(true),
+        4 => style.underline = Some(Underline::from_style(subparams.first().copied())),
+        5 | 6 => style.blink = Some(true),
         7 => style.reverse = Some(true),
         9 => style.strike = Some(true),
 
+        21 => style.underline = Some(Underline::Double),
         22 => {
           style.bold = Some(false);
           style.dim = Some(false);
         }
         23 => style.italic = Some(false),
-        24 => style.underline = Some(false),
+        24 => style.underline = Some(Underline::None),
         25 => style.blink = Some(false),
         27 => style.reverse = Some(false),
         29 => style.strike = Some(false),
 
-        38 if params[i + 1] == 2 => {
-          style.foreground = Some(Color::Rgb(params[i + 2], params[i + 3], params[i + 4]));
-          i += 5;
-          continue;
-        }
+        30..=37 => style.foreground = Some(Color::from_ansi((code - 30) as u8)),
+        38 => style.foreground = Color::extended(subparams, &mut params).or(style.foreground),
+        39 => style.foreground = Some(Color::Default),
 
-        38 if params[i + 1] == 5 => {
-          style.foreground = Some(Color::from_256(params[i + 2]));
-          i += 3;
-          continue;
-        }
+        40..=47 => style.background = Some(Color::from_ansi((code - 40) as u8)),
+        48 => style.background = Color::extended(subparams, &mut params).or(style.background),
+        49 => style.background = Some(Color::Default),
 
-        48 if params[i + 1] == 2 => {
-          style.background = Some(Color::Rgb(params[i + 2], params[i + 3], params[i + 4]));
-          i += 5;
-          continue;
-        }
+        53 => style.overline = Some(true),
+        55 => style.overline = Some(false),
 
-        48 if params[i + 1] == 5 => {
-          style.background = Some(Color::from_256(params[i + 2]));
-          i += 3;
-          continue;
-        }
+        58 => style.underline_color = Color::extended(subparams, &mut params).or(style.underline_color),
+        59 => style.underline_color = Some(Color::Default),
+
+        90..=97 => style.foreground = Some(Color::from_ansi((code - 90 + 8) as u8)),
+        100..=107 => style.background = Some(Color::from_ansi((code - 100 + 8) as u8)),
 
-        38 | 48 => {
-          return Err(anyhow::anyhow!(
-            "unknown color mode {} in sequence: {params:?}",
-            params[i + 1]
-          ));
+        // conceal, fonts, frames and other rarely supported attributes
+        _ => (),
+      }
+    }
+
+    style
+  }
+}
+
+/// The squared distance between two colours, treating their components as coordinates.
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+  let component = |a: u8, b: u8| u32::from(a.abs_diff(b)).pow(2);
+
+  component(a.0, b.0) + component(a.1, b.1) + component(a.2, b.2)
+}
+
+#[cfg(test)]
+mod tests {
+  use vte::{Params, Perform};
+
+  use super::{Color, Palette, Style, Underline};
+  use crate::args::ColorDepth;
+
+  /// The style set by the last SGR sequence in `sequence`.
+  fn sgr(sequence: &str) -> Style {
+    struct Sgr(Option<Style>);
+
+    impl Perform for Sgr {
+      fn csi_dispatch(&mut self, params: &Params, _: &[u8], _: bool, action: char) {
+        if action == 'm' {
+          self.0 = Some(Style::from_sgr(params));
         }
+      }
+    }
+
+    let mut performer = Sgr(None);
+    vte::Parser::new().advance(&mut performer, sequence.as_bytes());
+
+    performer.0.expect("no SGR sequence")
+  }
+
+  #[test]
+  fn omitted_parameters_reset() {
+    assert_eq!(sgr("\x1b[m"), Style::reset());
+    assert_eq!(
+      sgr("\x1b[;1m"),
+      Style {
+        bold: Some(true),
+        ..Style::reset()
+      }
+    );
+  }
 
-        30..=39 | 90..=97 => style.foreground = Some(Color::from(param)),
-        40..=49 | 100..=107 => style.background = Some(Color::from(param - 10)),
+  #[test]
+  fn extended_colors() {
+    let foreground = |color| Style {
+      foreground: Some(color),
+      ..Style::default()
+    };
 
-        param => println!("unknown param {param} in sequence: {params:?}, skipping"),
+    assert_eq!(sgr("\x1b[38:2::1:2:3m"), foreground(Color::Rgb(1, 2, 3)));
+    assert_eq!(sgr("\x1b[38:2:1:2:3m"), foreground(Color::Rgb(1, 2, 3)));
+    assert_eq!(sgr("\x1b[38;2;1;2;3m"), foreground(Color::Rgb(1, 2, 3)));
+    assert_eq!(sgr("\x1b[38;5;67m"), foreground(Color::from_256(67)));
+    assert_eq!(sgr("\x1b[38:5:1m"), foreground(Color::Red));
+    assert_eq!(
+      sgr("\x1b[48;5;244m"),
+      Style {
+        background: Some(Color::from_256(244)),
+        ..Style::default()
       }
+    );
+  }
 
-      i += 1;
+  #[test]
+  fn underlines() {
+    let underline = |underline| Style {
+      underline: Some(underline),
+      ..Style::default()
+    };
+
+    assert_eq!(sgr("\x1b[4m"), underline(Underline::Single));
+    assert_eq!(sgr("\x1b[4:3m"), underline(Underline::Curly));
+    assert_eq!(sgr("\x1b[4:0m"), underline(Underline::None));
+    assert_eq!(sgr("\x1b[21m"), underline(Underline::Double));
+    assert_eq!(sgr("\x1b[24m"), underline(Underline::None));
+  }
+
+  #[test]
+  fn underline_colors() {
+    let underline_color = |color| Style {
+      underline_color: Some(color),
+      ..Style::default()
+    };
+
+    assert_eq!(sgr("\x1b[58;5;1m"), underline_color(Color::Red));
+    assert_eq!(sgr("\x1b[58:2::1:2:3m"), underline_color(Color::Rgb(1, 2, 3)));
+    assert_eq!(sgr("\x1b[59m"), underline_color(Color::Default));
+  }
+
+  #[test]
+  fn overline() {
+    let overline = |overline| Style {
+      overline: Some(overline),
+      ..Style::default()
+    };
+
+    assert_eq!(sgr("\x1b[53m"), overline(true));
+    assert_eq!(sgr("\x1b[55m"), overline(false));
+  }
+
+  #[test]
+  fn truncated_colors() {
+    assert_eq!(sgr("\x1b[38;2;1m"), Style::default());
+    assert_eq!(sgr("\x1b[38;5m"), Style::default());
+    assert_eq!(sgr("\x1b[38m"), Style::default());
+    assert_eq!(sgr("\x1b[38:2:1:2m"), Style::default());
+  }
+
+  #[test]
+  fn out_of_range_values() {
+    assert_eq!(sgr("\x1b[38;5;256m"), Style::default());
+    assert_eq!(sgr("\x1b[38;2;1;2;300m"), Style::default());
+    assert_eq!(sgr("\x1b[38;5;99999999999m"), Style::default());
+    assert_eq!(sgr("\x1b[99999999999m"), Style::default());
+
+    // a bad component is still consumed, so what follows it is read as usual
+    assert_eq!(
+      sgr("\x1b[1;38;2;1;2;3000;4m"),
+      Style {
+        bold: Some(true),
+        underline: Some(Underline::Single),
+        ..Style::default()
+      }
+    );
+  }
+
+  #[test]
+  fn colour_cube() {
+    assert_eq!(Color::from_256(16), Color::Rgb(0, 0, 0));
+    assert_eq!(Color::from_256(17), Color::Rgb(0, 0, 95));
+    assert_eq!(Color::from_256(231), Color::Rgb(255, 255, 255));
+  }
+
+  #[test]
+  fn grayscale_ramp() {
+    assert_eq!(Color::from_256(232), Color::Rgb(8, 8, 8));
+    assert_eq!(Color::from_256(255), Color::Rgb(238, 238, 238));
+  }
+
+  #[test]
+  fn palette_entries() {
+    let mut palette = Palette::default();
+    palette
+      .set("1=#ff0000 bright-black=rgb:112233 foreground=#FFFFFF")
+      .unwrap();
+
+    assert_eq!(palette.base[1], Some(Color::Rgb(0xff, 0, 0)));
+    assert_eq!(palette.base[8], Some(Color::Rgb(0x11, 0x22, 0x33)));
+    assert_eq!(palette.foreground, Some(Color::Rgb(0xff, 0xff, 0xff)));
+    assert_eq!(palette.background, None);
+  }
+
+  #[test]
+  fn invalid_palette_entries() {
+    for pairs in ["16=#000000", "red=#ff", "red"] {
+      assert!(Palette::default().set(pairs).is_err(), "{pairs:?} was accepted");
     }
+  }
+
+  #[test]
+  fn quantised_faces() {
+    let red = Style {
+      foreground: Some(Color::Rgb(0xd0, 0x10, 0x10)),
+      ..Style::default()
+    };
+    let mut palette = Palette {
+      depth: ColorDepth::Colors16,
+      ..Palette::default()
+    };
+
+    assert_eq!(red.markup(&palette), "{red@Default}");
+
+    palette.faces = true;
+    assert_eq!(red.markup(&palette), "{@PopupRed}");
 
-    Ok(style)
+    palette.depth = ColorDepth::Truecolor;
+    assert_eq!(red.markup(&palette), "{rgb:D01010@PopupForeground}");
   }
 }
@@ -1,9 +1,15 @@
-use std::{fs, path::PathBuf, thread};
+use std::{env, fs, path::PathBuf, thread};
 
 use anyhow::{Context, Result};
-use tempfile::TempDir;
+use clap::ValueEnum;
 
-use crate::{args::OnErr, escape, fifo::Fifo, kakoune::Kakoune};
+use crate::{
+  args::OnErr,
+  escape,
+  fifo::Fifo,
+  kakoune::Kakoune,
+  tmux::{Server, Tmux},
+};
 
 pub struct Capture {
   kak_script: Option<String>,
@@ -11,39 +17,136 @@ pub struct Capture {
   status: Option<PathBuf>,
   stdout: Option<PathBuf>,
   stderr: Option<PathBuf>,
-  _tempdir: TempDir,
+
+  /// The directory holding the captured files, removed on drop unless persisted.
+  dir: PathBuf,
+  persist: bool,
+  /// The socket and name of the tmux session the capture was saved in.
+  session: Option<(PathBuf, String)>,
 }
 
 impl Capture {
+  const DIR_OPTION: &'static str = "kak-popup-capture";
+  const KAK_SCRIPT_OPTION: &'static str = "kak-popup-kak-script";
+  const ON_ERR_OPTION: &'static str = "kak-popup-on-err";
+
+  /// The start of the names of capture directories, so that `gc` can find them.
+  const DIR_PREFIX: &'static str = "kak-popup-capture-";
+  /// A file in the directory of a detached popup's capture, naming the tmux session it belongs to.
+  const DETACHED_FILE: &'static str = "detached";
+
   pub fn new(kak_script: Option<String>, on_err: OnErr) -> Result<Self> {
-    let tempdir = TempDir::new()?;
+    let dir = tempfile::Builder::new().prefix(Self::DIR_PREFIX).tempdir()?.keep();
+
+    Ok(Self::with_dir(dir, kak_script, on_err))
+  }
+
+  /// Restores the capture saved in a detached popup's tmux session.
+  pub fn load(tmux: &Tmux) -> Result<Self> {
+    let dir = tmux.user_option(Self::DIR_OPTION)?;
+    anyhow::ensure!(!dir.is_empty(), "popup {:?} has no capture", tmux.session);
+
+    let kak_script = Some(tmux.user_option(Self::KAK_SCRIPT_OPTION)?).filter(|script| !script.is_empty());
+    let on_err = OnErr::from_str(&tmux.user_option(Self::ON_ERR_OPTION)?, false).map_err(anyhow::Error::msg)?;
+
+    let mut capture = Self::with_dir(dir.into(), kak_script, on_err);
+    capture.session = Some((tmux.server().socket().to_path_buf(), tmux.session.clone()));
+
+    // attached again, so the capture is in use until this popup exits
+    let _ignore = fs::remove_file(capture.dir.join(Self::DETACHED_FILE));
+
+    Ok(capture)
+  }
+
+  /// Removes the captures of detached popups whose tmux session has gone, as it does when the command exits
+  /// while detached, returning their directories.
+  pub fn remove_orphans() -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+
+    for entry in fs::read_dir(env::temp_dir())? {
+      let dir = entry?.path();
+
+      let is_capture = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with(Self::DIR_PREFIX));
+
+      // only detached captures are left without a process to remove them
+      let Some(detached) = is_capture
+        .then(|| fs::read_to_string(dir.join(Self::DETACHED_FILE)).ok())
+        .flatten()
+      else {
+        continue;
+      };
+
+      let exists = detached
+        .split_once('\n')
+        .is_some_and(|(socket, session)| Server::new(socket.into()).has_session(session));
+
+      if !exists {
+        fs::remove_dir_all(&dir).with_context(|| format!("failed to remove {}", dir.display()))?;
+        removed.push(dir);
+      }
+    }
 
+    Ok(removed)
+  }
+
+  fn with_dir(dir: PathBuf, kak_script: Option<String>, on_err: OnErr) -> Self {
     let status = if Self::should_capture_status(on_err) {
-      Some(tempdir.path().join("status"))
+      Some(dir.join("status"))
     } else {
       None
     };
 
     let stderr = if Self::should_capture_stderr(on_err) {
-      Some(tempdir.path().join("stderr"))
+      Some(dir.join("stderr"))
     } else {
       None
     };
 
     let stdout = if kak_script.is_some() {
-      Some(tempdir.path().join("stdout"))
+      Some(dir.join("stdout"))
     } else {
       None
     };
 
-    Ok(Self {
+    Self {
       kak_script,
       on_err,
       status,
       stdout,
       stderr,
-      _tempdir: tempdir,
-    })
+      dir,
+      persist: false,
+      session: None,
+    }
+  }
+
+  /// Saves this capture in the tmux session so that it can be restored by [`Capture::load`].
+  pub fn save(&mut self, tmux: &Tmux) -> Result<()> {
+    tmux.set_user_option(Self::DIR_OPTION, &self.dir.to_string_lossy())?;
+    tmux.set_user_option(Self::KAK_SCRIPT_OPTION, self.kak_script.as_deref().unwrap_or_default())?;
+    tmux.set_user_option(Self::ON_ERR_OPTION, &self.on_err.to_string())?;
+
+    self.session = Some((tmux.server().socket().to_path_buf(), tmux.session.clone()));
+
+    Ok(())
+  }
+
+  /// Keeps the captured files around after this process exits, marked with the session they were saved in
+  /// so that `gc` can remove them if it goes away before being attached to.
+  pub fn persist(&mut self) -> Result<()> {
+    self.persist = true;
+
+    if let Some((socket, session)) = &self.session {
+      fs::write(
+        self.dir.join(Self::DETACHED_FILE),
+        format!("{}\n{session}", socket.display()),
+      )?;
+    }
+
+    Ok(())
   }
 
   fn should_capture_status(on_err: OnErr) -> bool {
@@ -100,7 +203,7 @@ impl Capture {
       })
       .unwrap_or_default();
 
-    let command = vec![command, &input, &args, &save_stdout, &save_stderr, &save_status].join(" ");
+    let command = [command, &input, &args, &save_stdout, &save_stderr, &save_status].join(" ");
 
     Ok(vec!["bash".into(), "-c".into(), command])
   }
@@ -145,3 +248,11 @@ impl Capture {
     Ok(())
   }
 }
+
+impl Drop for Capture {
+  fn drop(&mut self) {
+    if !self.persist {
+      let _ignore = fs::remove_dir_all(&self.dir);
+    }
+  }
+}
@@ -1,4 +1,8 @@
-use std::{fs, path::PathBuf};
+use std::{
+  fs::{self, File, OpenOptions},
+  io::BufReader,
+  path::PathBuf,
+};
 
 use anyhow::Result;
 use nix::{sys::stat::Mode, unistd};
@@ -43,6 +47,15 @@ impl Fifo {
     Ok(fs::read_to_string(&self.path)?)
   }
 
+  /// Opens the fifo to be read a line at a time for as long as the reader is kept. It's opened for writing
+  /// too, so that writers never wait for a reader, and the reader never sees the end of the file between
+  /// writers.
+  pub fn reader(&self) -> Result<BufReader<File>> {
+    let file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+
+    Ok(BufReader::new(file))
+  }
+
   pub fn write(&self, contents: impl AsRef<[u8]>) -> Result<()> {
     Ok(fs::write(&self.path, contents)?)
   }
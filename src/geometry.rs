@@ -3,7 +3,7 @@ use std::str::FromStr;
 use anyhow::Result;
 use serde::Deserialize;
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
 pub struct Point {
   pub x: usize,
   pub y: usize,
@@ -1,39 +1,152 @@
 use std::{
   io::Write,
   process::{Command, Stdio},
+  sync::mpsc::{self, Receiver, Sender},
+  thread,
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 use crate::escape;
 
+/// A handle on a kakoune session, which commands are sent to through a single delivery thread.
+///
+/// Kakoune closes a remote connection as soon as it has run the command sent on it, so there's no
+/// connection to keep open between commands. Instead, whatever is queued while one `kak -p` runs is sent
+/// together by the next, and every child is waited on before another is spawned.
 #[derive(Clone)]
 pub struct Kakoune {
   session: String,
   client: String,
   debug: bool,
+  queue: Sender<Delivery>,
+}
+
+/// A command waiting to be sent, and where to report whether it was.
+struct Delivery {
+  command: String,
+  delivered: Sender<Result<()>>,
 }
 
 impl Kakoune {
   pub fn new(session: String, client: String, debug: bool) -> Self {
-    Self { session, client, debug }
+    let (queue, receiver) = mpsc::channel();
+
+    let worker_session = session.clone();
+    thread::spawn(move || Self::deliver(&worker_session, &receiver));
+
+    Self {
+      session,
+      client,
+      debug,
+      queue,
+    }
+  }
+
+  /// The running kakoune sessions, as listed by `kak -l`.
+  pub fn sessions() -> Result<Vec<String>> {
+    let output = Command::new("kak").arg("-l").output().context("kak -l")?;
+
+    anyhow::ensure!(
+      output.status.success(),
+      "kak -l exited with non-zero status: {}",
+      output.status
+    );
+
+    Ok(
+      String::from_utf8(output.stdout)?
+        .lines()
+        .filter(|session| !session.ends_with(" (dead)"))
+        .map(String::from)
+        .collect(),
+    )
+  }
+
+  pub fn session(&self) -> &str {
+    &self.session
+  }
+
+  pub fn client(&self) -> &str {
+    &self.client
+  }
+
+  /// Queues `command` and waits until it has been delivered to the session.
+  fn command(&self, command: impl AsRef<str>) -> Result<()> {
+    self
+      .queue(command)?
+      .recv()
+      .map_err(|_| anyhow::anyhow!("kakoune connection closed"))?
   }
 
-  fn command(&self, command: impl AsRef<[u8]>) -> Result<()> {
+  /// Queues `command` without waiting, so it's sent along with whatever is queued next. Whether it was
+  /// delivered can be read from the returned channel.
+  fn queue(&self, command: impl AsRef<str>) -> Result<Receiver<Result<()>>> {
+    let (delivered, result) = mpsc::channel();
+
+    // commands are batched, so one failing must not prevent the others from running
+    let command = format!(
+      "try {} catch %{{ echo -debug \"kak-popup: %val{{error}}\" }}",
+      escape::kak(command)
+    );
+
+    self
+      .queue
+      .send(Delivery { command, delivered })
+      .map_err(|_| anyhow::anyhow!("kakoune connection closed"))?;
+
+    Ok(result)
+  }
+
+  /// Sends queued commands until every handle is dropped, batching whatever queued up during the previous
+  /// send into a single `kak -p`.
+  fn deliver(session: &str, queue: &Receiver<Delivery>) {
+    while let Ok(delivery) = queue.recv() {
+      let batch: Vec<_> = [delivery].into_iter().chain(queue.try_iter()).collect();
+      let script = batch
+        .iter()
+        .map(|delivery| delivery.command.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+      let result = Self::send(session, &script);
+
+      for delivery in batch {
+        let result = result.as_ref().map(|_| ()).map_err(|err| anyhow::anyhow!("{err:#}"));
+        let _ignore = delivery.delivered.send(result);
+      }
+    }
+  }
+
+  fn send(session: &str, script: &str) -> Result<()> {
     let mut child = Command::new("kak")
-      .args(["-p", &self.session])
+      .args(["-p", session])
       .stdin(Stdio::piped())
-      .spawn()?;
+      .stdout(Stdio::null())
+      .stderr(Stdio::piped())
+      .spawn()
+      .context("kak -p")?;
 
-    child
+    // stdin is closed before waiting, as kakoune only runs the script once it's complete
+    let written = child
       .stdin
       .take()
-      .ok_or(anyhow::anyhow!("no stdin"))?
-      .write_all(command.as_ref())?;
+      .ok_or(anyhow::anyhow!("no stdin"))
+      .and_then(|mut stdin| Ok(stdin.write_all(script.as_bytes())?));
 
-    Ok(())
+    let output = child.wait_with_output().context("kak -p")?;
+
+    anyhow::ensure!(
+      output.status.success(),
+      "kak -p {session} exited with non-zero status: {}, err: {}",
+      output.status,
+      String::from_utf8_lossy(&output.stderr).trim(),
+    );
+
+    written
   }
 
+  /// Logs `message` to the `*debug*` buffer. It's already printed, so it isn't waited on and rides along
+  /// with the next command instead.
   pub fn debug(&self, message: impl AsRef<str>) -> Result<()> {
     if !self.debug {
       return Ok(());
@@ -43,7 +156,7 @@ impl Kakoune {
 
     let message = escape::kak(message);
 
-    self.command(format!("echo -debug 'kak-popup:' {message}").as_bytes())?;
+    self.queue(format!("echo -debug 'kak-popup:' {message}"))?;
 
     Ok(())
   }
@@ -51,16 +164,22 @@ impl Kakoune {
   pub fn eval(&self, command: impl AsRef<str>) -> Result<()> {
     let command = escape::kak(command);
 
-    self.command(format!("evaluate-commands -client '{}' {command}", self.client).as_bytes())?;
+    self.command(format!("evaluate-commands -client '{}' {command}", self.client))?;
 
     Ok(())
   }
 
+  /// Runs `f`, logging any error, and waits for the debug lines still queued so none are lost on exit.
   pub fn debug_on_error(&self, f: impl FnOnce() -> Result<()>) -> Result<()> {
     if let Err(err) = f() {
       self.debug(format!("error: {err:?}"))?;
     }
 
+    if self.debug {
+      // delivered in order, so the debug lines are sent no later than this
+      self.command("nop")?;
+    }
+
     Ok(())
   }
 }
@@ -1,4 +1,7 @@
 mod args;
+mod backend;
+#[cfg(feature = "bench")]
+mod bench;
 mod buffer;
 mod capture;
 mod escape;
@@ -6,22 +9,34 @@ mod fifo;
 mod geometry;
 mod kakoune;
 mod popup;
+mod pty;
 mod threads;
 mod tmux;
+mod view;
+mod yank;
 
-use std::{env, fs::File, os::unix::ffi::OsStringExt, thread, time::Duration};
+use std::{env, fs::File, os::unix::ffi::OsStringExt, path::PathBuf, thread, time::Duration};
 
 use anyhow::{Context, Result};
 use clap::Parser;
 use daemonize::Daemonize;
+use encoding_rs::UTF_8;
 use tempfile::TempDir;
 
 use self::{
-  args::{Args, Command, Popup as PopupArgs},
+  args::{
+    Args, Attach as AttachArgs, Backend as BackendKind, Client as ClientArgs, Command, List as ListArgs,
+    Popup as PopupArgs, Yank as YankArgs,
+  },
+  buffer::Palette,
   capture::Capture,
   fifo::Fifo,
+  geometry::Size,
   kakoune::Kakoune,
-  popup::Popup,
+  popup::{Popup, Settings},
+  threads::{Exit, Keymap, Rate},
+  tmux::Server,
+  yank::{Extent, Target},
 };
 
 fn daemonize() -> Result<TempDir> {
@@ -41,53 +56,268 @@ fn init() {
   println!("{kak_script}", kak_script = include_str!("../rc/popup.kak"));
 }
 
+/// The tmux server at `socket`, or the one private to `kak_session`.
+fn server(socket: Option<PathBuf>, kak_session: &str) -> Result<Server> {
+  match socket {
+    Some(socket) => Ok(Server::new(socket)),
+    None => Server::for_kak_session(kak_session),
+  }
+}
+
+fn list(args: ListArgs) -> Result<()> {
+  let servers = match (args.tmux_socket, &args.kak_session) {
+    (Some(socket), _) => vec![Server::new(socket)],
+    (None, Some(kak_session)) => vec![Server::for_kak_session(kak_session)?],
+    (None, None) => Server::all()?,
+  };
+
+  for server in servers {
+    for listing in Popup::list(&server)? {
+      if args
+        .kak_session
+        .as_ref()
+        .is_some_and(|session| *session != listing.kak_session)
+      {
+        continue;
+      }
+
+      println!(
+        "{name} {command} {size} {kak_session} {kak_client}",
+        name = escape::kak(&listing.name),
+        command = escape::kak(&listing.command),
+        size = escape::kak(format!("{}x{}", listing.size.width, listing.size.height)),
+        kak_session = escape::kak(&listing.kak_session),
+        kak_client = escape::kak(&listing.kak_client),
+      );
+    }
+  }
+
+  Ok(())
+}
+
+fn gc() -> Result<()> {
+  let kak_sessions = Kakoune::sessions()?;
+
+  for dir in Capture::remove_orphans()? {
+    println!("removed capture {}, its popup exited while detached", dir.display());
+  }
+
+  for server in Server::all()? {
+    let socket = server.socket().display();
+
+    if !server.is_running() {
+      server.remove()?;
+      println!("removed stale socket {socket}");
+    } else if server
+      .kak_session()
+      .is_some_and(|session| !kak_sessions.iter().any(|kak_session| kak_session == session))
+    {
+      server.kill()?;
+      println!("killed server {socket}, its kakoune session has exited");
+    }
+  }
+
+  Ok(())
+}
+
 fn popup(args: PopupArgs) -> Result<()> {
-  let _: Option<TempDir> = if args.daemonize { Some(daemonize()?) } else { None };
+  let client = args.client;
+  let settings = settings(&client)?;
+
+  let server = server(client.tmux_socket, &client.kak_session)?;
+
+  // checked before daemonizing so that kakoune sees the failure
+  if let Some(name) = &args.name {
+    anyhow::ensure!(
+      matches!(args.backend, BackendKind::Tmux),
+      "only tmux popups can be named"
+    );
+    server.validate_name(name)?;
+  }
+
+  // tmux decodes output itself, so only an in-process terminal can decode other encodings
+  anyhow::ensure!(
+    matches!(args.backend, BackendKind::Pty) || args.encoding == UTF_8,
+    "only pty popups support encodings other than UTF-8"
+  );
+
+  let _: Option<TempDir> = if client.daemonize { Some(daemonize()?) } else { None };
 
-  let kakoune = Kakoune::new(args.kak_session, args.kak_client, args.debug);
+  let kakoune = Kakoune::new(client.kak_session, client.kak_client, client.debug);
 
   kakoune.debug_on_error(|| {
-    let capture = Capture::new(args.kak_script, args.on_err)?;
+    let mut capture = Capture::new(args.kak_script, args.on_err)?;
     let keys_fifo = Fifo::new("keys")?;
     let command = capture.command(&args.command, &args.args, args.input.map(OsStringExt::into_vec))?;
 
-    Popup::new(
-      kakoune.clone(),
-      keys_fifo,
-      args.title,
-      args.height,
-      args.width,
-      args.padding,
+    let size = Size {
+      height: client.height,
+      width: client.width,
+    };
+
+    let backend = backend::spawn(
+      args.backend,
+      server,
+      args.name,
       &command,
+      size.padded(client.padding)?,
+      args.encoding,
     )
-    .context("Popup::new")?
-    .show()
-    .context("Popup::show")?;
+    .context("backend::spawn")?;
+    let popup = Popup::new(kakoune.clone(), keys_fifo, backend, args.title, size, settings).context("Popup::new")?;
 
-    capture.handle_output(&kakoune).context("Capture::handle_output")?;
+    popup.set_command(
+      &[args.command]
+        .into_iter()
+        .chain(args.args)
+        .collect::<Vec<_>>()
+        .join(" "),
+    )?;
 
-    // allow any remaining fifos to be flushed
-    thread::sleep(Duration::from_secs(1));
+    // only tmux sessions can be detached from, and so need their capture saved
+    if let Some(tmux) = popup.tmux() {
+      capture.save(tmux).context("Capture::save")?;
+    }
 
-    Ok(())
+    show(popup, &mut capture, &kakoune)
   })?;
 
   Ok(())
 }
 
+fn attach(args: AttachArgs) -> Result<()> {
+  let client = args.client;
+  let settings = settings(&client)?;
+
+  let server = server(client.tmux_socket, &client.kak_session)?;
+
+  // checked before daemonizing so that kakoune sees the failure
+  anyhow::ensure!(server.has_session(&args.name), "no popup named {:?}", args.name);
+
+  let _: Option<TempDir> = if client.daemonize { Some(daemonize()?) } else { None };
+
+  let kakoune = Kakoune::new(client.kak_session, client.kak_client, client.debug);
+
+  kakoune.debug_on_error(|| {
+    let keys_fifo = Fifo::new("keys")?;
+
+    let size = Size {
+      height: client.height,
+      width: client.width,
+    };
+
+    let popup =
+      Popup::attach(kakoune.clone(), keys_fifo, server, &args.name, size, settings).context("Popup::attach")?;
+
+    let tmux = popup
+      .tmux()
+      .ok_or(anyhow::anyhow!("attached popup has no tmux session"))?;
+    let mut capture = Capture::load(tmux).context("Capture::load")?;
+
+    show(popup, &mut capture, &kakoune)
+  })?;
+
+  Ok(())
+}
+
+fn yank(args: YankArgs) -> Result<()> {
+  let server = server(args.tmux_socket, &args.kak_session)?;
+  let kakoune = Kakoune::new(args.kak_session, args.kak_client, false);
+
+  let extent = if args.history {
+    Extent::History
+  } else {
+    Extent::Screen { scroll: 0 }
+  };
+  let target = if args.buffer {
+    Target::Buffer
+  } else {
+    Target::Register(args.register)
+  };
+
+  // run in the background by kakoune, so failures are reported there rather than on stderr
+  if let Err(err) = Popup::yank(&kakoune, server, &args.name, extent, &target) {
+    kakoune.eval(format!(
+      "
+        echo -debug {message}
+        echo -markup '{{Error}}failed to yank popup, see *debug* for details'
+      ",
+      message = escape::kak(format!("kak-popup: failed to yank {:?}: {err:?}", args.name)),
+    ))?;
+  }
+
+  Ok(())
+}
+
+fn show(mut popup: Popup, capture: &mut Capture, kakoune: &Kakoune) -> Result<()> {
+  let exit = popup.show().context("Popup::show")?;
+
+  // the tmux session must be gone before its output is handled
+  drop(popup);
+
+  match exit {
+    Exit::Quit => capture.handle_output(kakoune).context("Capture::handle_output")?,
+    Exit::Detach => capture.persist().context("Capture::persist")?,
+  }
+
+  // allow any remaining fifos to be flushed
+  thread::sleep(Duration::from_secs(1));
+
+  Ok(())
+}
+
+fn settings(client: &ClientArgs) -> Result<Settings> {
+  let mut keymap = Keymap::default();
+
+  for pairs in &client.keymap {
+    keymap.bind(pairs)?;
+  }
+
+  let mut palette = Palette::default();
+
+  for pairs in &client.palette {
+    palette.set(pairs)?;
+  }
+
+  palette.faces = client.faces;
+  palette.depth = client.color_depth;
+
+  Ok(Settings {
+    padding: client.padding,
+    refresh_rate: Rate {
+      min: Duration::from_millis(client.min_refresh),
+      max: Duration::from_millis(client.max_refresh.max(client.min_refresh)),
+    },
+    keymap,
+    palette,
+  })
+}
+
+/// A heuristic to ignore padding if it's too large relative to height or width
+fn clamp_padding(client: &mut ClientArgs) {
+  if 3 * client.padding >= client.height || 3 * client.padding >= client.width {
+    client.padding = 4
+  }
+}
+
 fn main() -> Result<()> {
   let args = Args::parse();
 
   match args.command {
     Command::Init => init(),
+    Command::List(args) => list(args)?,
+    Command::Gc => gc()?,
+    #[cfg(feature = "bench")]
+    Command::Bench(args) => bench::bench(&args.captures, args.iterations)?,
+    Command::Yank(args) => yank(args)?,
     Command::Popup(mut args) => {
-      // A heuristic to ignore padding if it's too large relative to height or width
-      if 3 * args.padding >= args.height || 3 * args.padding >= args.width {
-        args.padding = 4
-      }
-
+      clamp_padding(&mut args.client);
       popup(args)?;
     }
+    Command::Attach(mut args) => {
+      clamp_padding(&mut args.client);
+      attach(args)?;
+    }
   }
 
   Ok(())
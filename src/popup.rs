@@ -1,62 +1,172 @@
-use std::thread;
+use std::{sync::Arc, thread};
 
 use anyhow::Result;
 
 use crate::{
+  backend::Backend,
+  buffer::Palette,
+  escape,
   fifo::Fifo,
   geometry::Size,
   kakoune::Kakoune,
-  threads::{Keys, Quit, Refresh, Resize, Spawn},
-  tmux::Tmux,
+  threads::{Exit, Keymap, Keys, Paste, Quit, Rate, Refresh, Resize, Spawn},
+  tmux::{Server, Tmux},
+  view::View,
+  yank::{self, Extent, Target},
 };
 
+/// How a popup is shown and controlled, which unlike its title isn't kept when it's detached.
+pub struct Settings {
+  /// The amount of padding around the height and width of the popup.
+  pub padding: usize,
+  pub refresh_rate: Rate,
+  pub keymap: Keymap,
+  pub palette: Palette,
+}
+
 pub struct Popup {
-  tmux: Tmux,
+  backend: Arc<dyn Backend>,
   kakoune: Kakoune,
 
   title: Option<String>,
-  padding: usize,
+  settings: Settings,
+  /// The size of the kakoune window, which the popup is sized to fit.
+  window: Size,
 
   keys_fifo: Fifo,
   resize_fifo: Fifo,
-  commands_fifo: Fifo,
+  paste_fifo: Fifo,
+
+  detached: bool,
+}
+
+/// A running popup, as reported by `kak-popup list`.
+pub struct Listing {
+  pub name: String,
+  pub command: String,
+  pub size: Size,
+  pub kak_session: String,
+  pub kak_client: String,
 }
 
 impl Popup {
+  const TITLE_OPTION: &'static str = "kak-popup-title";
+  const COMMAND_OPTION: &'static str = "kak-popup-command";
+  const KAK_SESSION_OPTION: &'static str = "kak-popup-kak-session";
+  const KAK_CLIENT_OPTION: &'static str = "kak-popup-kak-client";
+
   pub fn new(
     kakoune: Kakoune,
     keys_fifo: Fifo,
+    backend: Arc<dyn Backend>,
     title: Option<String>,
-    height: usize,
-    width: usize,
-    padding: usize,
-    command: &[String],
+    window: Size,
+    settings: Settings,
+  ) -> Result<Self> {
+    if let Some(tmux) = backend.as_tmux() {
+      tmux.set_user_option(Self::TITLE_OPTION, title.as_deref().unwrap_or_default())?;
+    }
+
+    Self::with_backend(backend, kakoune, keys_fifo, title, window, settings)
+  }
+
+  /// Re-opens the popup left running in the tmux session `name` by a detach.
+  pub fn attach(
+    kakoune: Kakoune,
+    keys_fifo: Fifo,
+    server: Server,
+    name: &str,
+    window: Size,
+    settings: Settings,
   ) -> Result<Self> {
-    let size = Size { height, width }.padded(padding)?;
+    let tmux = Tmux::attach(server, name, window.padded(settings.padding)?)?;
+    let title = Some(tmux.user_option(Self::TITLE_OPTION)?).filter(|title| !title.is_empty());
+
+    kakoune.eval(format!(
+      "set-option -remove global popup_detached {}",
+      escape::kak(name)
+    ))?;
+
+    Self::with_backend(Arc::new(tmux), kakoune, keys_fifo, title, window, settings)
+  }
+
+  /// Lists the popups on `server`, whether shown or detached.
+  pub fn list(server: &Server) -> Result<Vec<Listing>> {
+    server
+      .sessions()?
+      .into_iter()
+      .map(|tmux| {
+        Ok(Listing {
+          command: tmux.user_option(Self::COMMAND_OPTION)?,
+          size: tmux.size(),
+          kak_session: tmux.user_option(Self::KAK_SESSION_OPTION)?,
+          kak_client: tmux.user_option(Self::KAK_CLIENT_OPTION)?,
+          name: tmux.session,
+        })
+      })
+      .collect()
+  }
+
+  /// Yanks the text of the popup in the tmux session `name`, whether it's shown or detached.
+  pub fn yank(kakoune: &Kakoune, server: Server, name: &str, extent: Extent, target: &Target) -> Result<()> {
+    let tmux = Tmux::inspect(server, name)?;
+    let title = tmux.user_option(Self::TITLE_OPTION)?;
+
+    yank::yank(kakoune, &tmux, &title, extent, target)
+  }
+
+  /// Records the command shown by `kak-popup list`.
+  pub fn set_command(&self, command: &str) -> Result<()> {
+    match self.tmux() {
+      Some(tmux) => tmux.set_user_option(Self::COMMAND_OPTION, command),
+      None => Ok(()),
+    }
+  }
+
+  fn with_backend(
+    backend: Arc<dyn Backend>,
+    kakoune: Kakoune,
+    keys_fifo: Fifo,
+    title: Option<String>,
+    window: Size,
+    settings: Settings,
+  ) -> Result<Self> {
+    if let Some(tmux) = backend.as_tmux() {
+      tmux.set_user_option(Self::KAK_SESSION_OPTION, kakoune.session())?;
+      tmux.set_user_option(Self::KAK_CLIENT_OPTION, kakoune.client())?;
+    }
 
     Ok(Self {
-      tmux: Tmux::new(command, size)?,
+      backend,
       kakoune,
 
       title,
-      padding,
+      settings,
+      window,
 
       keys_fifo,
       resize_fifo: Fifo::new("resize")?,
-      commands_fifo: Fifo::new("commands")?,
+      paste_fifo: Fifo::new("paste")?,
+
+      detached: false,
     })
   }
 
+  /// The tmux session backing this popup, if it uses the tmux backend.
+  pub fn tmux(&self) -> Option<&Tmux> {
+    self.backend.as_tmux()
+  }
+
   fn set_options(&self) -> Result<()> {
     self.kakoune.eval(format!(
       "
         set-option window popup_keys_fifo {keys_fifo}
         set-option window popup_resize_fifo {resize_fifo}
-        set-option window popup_commands_fifo '%file{{{commands_fifo}}}'
+        set-option window popup_paste_fifo {paste_fifo}
       ",
       keys_fifo = self.keys_fifo.path_str()?,
       resize_fifo = self.resize_fifo.path_str()?,
-      commands_fifo = self.commands_fifo.path_str()?,
+      paste_fifo = self.paste_fifo.path_str()?,
     ))?;
 
     Ok(())
@@ -77,58 +187,76 @@ impl Popup {
     Ok(())
   }
 
-  pub fn show(&self) -> Result<()> {
+  pub fn show(&mut self) -> Result<Exit> {
     self.set_options()?;
     self.set_resize_hook()?;
     self.kakoune.eval("popup-style-modal")?;
 
     let quit = Quit::new();
+    let view = View::new(self.title.clone(), self.window, self.settings.padding);
 
-    let refresh = Refresh::new(self.kakoune.clone(), self.tmux.clone(), self.title.clone());
+    let refresh = Refresh::new(self.kakoune.clone(), &self.settings, self.backend.clone(), view.clone());
 
     let keys = Keys::new(
-      &self.kakoune,
-      self.padding,
-      self.tmux.clone(),
+      self.kakoune.clone(),
+      &self.settings,
+      self.backend.clone(),
       self.keys_fifo.clone(),
-      self.commands_fifo.clone(),
       refresh.sender.clone(),
+      view.clone(),
     )?;
 
     let resize = Resize::new(
-      self.padding,
-      self.tmux.clone(),
+      self.backend.clone(),
       self.resize_fifo.clone(),
       refresh.sender.clone(),
+      view.clone(),
     );
 
+    let paste = Paste::new(self.backend.clone(), self.paste_fifo.clone(), refresh.sender.clone());
+
     keys.spawn(self.kakoune.clone(), quit.clone());
+    paste.spawn(self.kakoune.clone(), quit.clone());
     resize.spawn(self.kakoune.clone(), quit.clone());
     refresh.spawn(self.kakoune.clone(), quit.clone());
 
     self.kakoune.debug("waiting for quit")?;
 
-    quit.wait();
+    let exit = quit.wait();
 
     self.kakoune.debug("done waiting")?;
 
     self.hide()?;
     self.flush_fifos();
 
-    Ok(())
+    if let (Exit::Detach, Some(tmux)) = (exit, self.tmux()) {
+      self.kakoune.eval(format!(
+        "
+          set-option -add global popup_detached {name}
+          echo -markup {{Information}}popup {name} detached, use popup-attach to re-open it
+        ",
+        name = escape::kak(&tmux.session),
+      ))?;
+
+      self.detached = true;
+    }
+
+    Ok(exit)
   }
 
   fn hide(&self) -> Result<()> {
-    self.kakoune.eval(format!(
+    // the fifo is unset first, so that the key ending the pending on-key fails to be written and
+    // doesn't capture keys again
+    self.kakoune.eval(
       "
-        execute-keys {}
+        unset-option window popup_keys_fifo
+        unset-option window popup_paste_fifo
+        execute-keys <esc>
         info -style modal
         popup-unstyle-modal
-        unset-option window popup_keys_fifo
         remove-hooks window popup
       ",
-      Keys::QUIT_KEY
-    ))?;
+    )?;
 
     Ok(())
   }
@@ -136,23 +264,23 @@ impl Popup {
   fn flush_fifos(&self) {
     let keys_fifo = self.keys_fifo.clone();
     let resize_fifo = self.resize_fifo.clone();
-    let commands_fifo = self.commands_fifo.clone();
 
     thread::spawn(move || keys_fifo.read());
     thread::spawn(move || resize_fifo.read());
-    thread::spawn(move || commands_fifo.write("nop"));
   }
 }
 
 impl Drop for Popup {
   fn drop(&mut self) {
-    if let Err(err) = self.tmux.kill() {
-      self
-        .kakoune
-        .debug(format!("failed to kill tmux session {}: {err:?}", self.tmux.session))
-        .expect("debug");
+    if self.detached {
+      let _ignore = self.kakoune.debug("detaching popup");
+      return;
+    }
+
+    if let Err(err) = self.backend.kill() {
+      let _ignore = self.kakoune.debug(format!("failed to kill popup terminal: {err:?}"));
     }
 
-    self.kakoune.debug("exiting popup").expect("debug");
+    let _ignore = self.kakoune.debug("exiting popup");
   }
 }
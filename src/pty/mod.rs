@@ -0,0 +1,399 @@
+mod screen;
+
+use std::{
+  fs::File,
+  io::{Read, Write},
+  os::unix::{
+    io::{AsRawFd, FromRawFd, RawFd},
+    process::CommandExt,
+  },
+  process::{Child, Command, Stdio},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::Sender,
+    Arc,
+  },
+  thread,
+};
+
+use anyhow::Result;
+use encoding_rs::{Decoder, Encoding};
+use nix::{
+  libc,
+  pty::{self, Winsize},
+  sys::signal::{self, Signal},
+  unistd::{self, Pid},
+};
+use parking_lot::Mutex;
+
+use self::screen::Screen;
+use crate::{
+  backend::{Backend, DisplayInfo, Event, Key, Modifiers},
+  geometry::Size,
+};
+
+/// A terminal emulated in-process, running its command in a pseudo-terminal.
+#[derive(Clone)]
+pub struct Pty {
+  inner: Arc<Inner>,
+}
+
+struct Inner {
+  master: Mutex<File>,
+  screen: Mutex<Screen>,
+  child: Mutex<Child>,
+  exited: AtomicBool,
+  subscribers: Mutex<Vec<Sender<Event>>>,
+}
+
+impl Pty {
+  pub fn new(command: &[String], size: Size, encoding: &'static Encoding) -> Result<Self> {
+    let (program, args) = command.split_first().ok_or(anyhow::anyhow!("empty command"))?;
+    let pty = pty::openpty(&winsize(size), None)?;
+
+    // SAFETY: openpty returns newly opened descriptors that nothing else owns
+    let master = unsafe { File::from_raw_fd(pty.master) };
+    let slave = unsafe { File::from_raw_fd(pty.slave) };
+
+    let mut command = Command::new(program);
+    command
+      .args(args)
+      .env("TERM", "xterm-256color")
+      .env_remove("TMUX")
+      .stdin(Stdio::from(slave.try_clone()?))
+      .stdout(Stdio::from(slave.try_clone()?))
+      .stderr(Stdio::from(slave));
+
+    // SAFETY: only async-signal-safe functions are called between fork and exec
+    unsafe {
+      command.pre_exec(|| {
+        unistd::setsid()?;
+
+        if libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+          return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(())
+      });
+    }
+
+    let pty = Self {
+      inner: Arc::new(Inner {
+        child: Mutex::new(command.spawn()?),
+        master: Mutex::new(master.try_clone()?),
+        screen: Mutex::new(Screen::new(size)),
+        exited: AtomicBool::new(false),
+        subscribers: Mutex::new(Vec::new()),
+      }),
+    };
+
+    let reader = pty.clone();
+    thread::spawn(move || reader.read(master, encoding));
+
+    Ok(pty)
+  }
+
+  /// Feeds the program's output to the screen until the program exits, decoding it from `encoding` into
+  /// the UTF-8 the parser expects.
+  fn read(&self, mut master: File, encoding: &'static Encoding) {
+    let mut parser = vte::Parser::new();
+    let mut decoder = OutputDecoder::new(encoding);
+    let mut buf = [0; 4096];
+
+    // reading fails with EIO once every process holding the pty has exited
+    while let Ok(n @ 1..) = master.read(&mut buf) {
+      let text = decoder.decode(&buf[..n]);
+
+      let responses = {
+        let mut screen = self.inner.screen.lock();
+        parser.advance(&mut *screen, text.as_bytes());
+        std::mem::take(&mut screen.responses)
+      };
+
+      if !responses.is_empty() {
+        let _ignore = self.write(&responses);
+      }
+
+      self.notify();
+    }
+
+    self.inner.exited.store(true, Ordering::SeqCst);
+    self.notify();
+  }
+
+  fn notify(&self) {
+    self
+      .inner
+      .subscribers
+      .lock()
+      .retain(|events| events.send(Event::Output).is_ok());
+  }
+
+  fn write(&self, bytes: &[u8]) -> Result<()> {
+    Ok(self.inner.master.lock().write_all(bytes)?)
+  }
+
+  fn ensure_running(&self) -> Result<()> {
+    anyhow::ensure!(!self.inner.exited.load(Ordering::SeqCst), "pty command exited");
+
+    Ok(())
+  }
+}
+
+/// Decodes the program's output into UTF-8, one read at a time.
+struct OutputDecoder {
+  decoder: Decoder,
+  text: String,
+}
+
+impl OutputDecoder {
+  fn new(encoding: &'static Encoding) -> Self {
+    Self {
+      decoder: encoding.new_decoder_without_bom_handling(),
+      text: String::new(),
+    }
+  }
+
+  /// Decodes the next read. Malformed input becomes U+FFFD, and a sequence split across reads is completed
+  /// by the next.
+  fn decode(&mut self, bytes: &[u8]) -> &str {
+    self.text.clear();
+    self.text.reserve(
+      self
+        .decoder
+        .max_utf8_buffer_length(bytes.len())
+        .unwrap_or(4 * bytes.len()),
+    );
+
+    let _ = self.decoder.decode_to_string(bytes, &mut self.text, false);
+
+    &self.text
+  }
+}
+
+impl Backend for Pty {
+  fn display_info(&self) -> Result<DisplayInfo> {
+    self.ensure_running()?;
+
+    let screen = self.inner.screen.lock();
+
+    Ok(DisplayInfo {
+      size: screen.size(),
+      cursor: screen.cursor(),
+      history_size: screen.history_size(),
+      mouse: screen.mouse,
+    })
+  }
+
+  fn capture_pane(&self, scroll: usize) -> Result<Vec<u8>> {
+    self.ensure_running()?;
+
+    Ok(self.inner.screen.lock().capture(scroll))
+  }
+
+  fn capture_history(&self) -> Result<Vec<u8>> {
+    self.ensure_running()?;
+
+    Ok(self.inner.screen.lock().capture_history())
+  }
+
+  fn send_keys(&self, keys: Vec<Key>) -> Result<()> {
+    self.ensure_running()?;
+
+    let application_cursor = self.inner.screen.lock().application_cursor;
+    let bytes: Vec<u8> = keys
+      .into_iter()
+      .flat_map(|key| match key {
+        Key::Key(key) => key_bytes(&key, application_cursor),
+        Key::Bytes(bytes) => bytes,
+      })
+      .collect();
+
+    // a single write, so the program reads a burst of keys as it would from a terminal
+    self.write(&bytes)
+  }
+
+  fn paste(&self, text: &str) -> Result<()> {
+    self.ensure_running()?;
+
+    // newlines are typed as carriage returns, as tmux pastes them
+    let text = text.replace("\r\n", "\r").replace('\n', "\r");
+
+    if !self.inner.screen.lock().bracketed_paste {
+      return self.write(text.as_bytes());
+    }
+
+    // the end marker can't appear in the text, or the program would take the rest as typed
+    let text = text.replace("\x1b[201~", "");
+
+    self.write(format!("\x1b[200~{text}\x1b[201~").as_bytes())
+  }
+
+  fn set_size(&self, size: Size) -> Result<()> {
+    self.inner.screen.lock().resize(size);
+
+    set_winsize(self.inner.master.lock().as_raw_fd(), size)
+  }
+
+  fn kill(&self) -> Result<()> {
+    let mut child = self.inner.child.lock();
+
+    // the command runs in its own session, so this reaches everything it started
+    let _ignore = signal::killpg(Pid::from_raw(child.id() as i32), Signal::SIGHUP);
+    let _ignore = child.kill();
+    child.wait()?;
+
+    Ok(())
+  }
+
+  fn subscribe(&self, events: Sender<Event>) {
+    self.inner.subscribers.lock().push(events);
+  }
+}
+
+fn winsize(size: Size) -> Winsize {
+  Winsize {
+    ws_row: size.height as u16,
+    ws_col: size.width as u16,
+    ws_xpixel: 0,
+    ws_ypixel: 0,
+  }
+}
+
+fn set_winsize(fd: RawFd, size: Size) -> Result<()> {
+  // SAFETY: TIOCSWINSZ only reads the provided winsize
+  if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &winsize(size)) } < 0 {
+    return Err(std::io::Error::last_os_error().into());
+  }
+
+  Ok(())
+}
+
+/// Translates a tmux key name, as produced for `send-keys`, to the bytes a terminal would send.
+fn key_bytes(key: &str, application_cursor: bool) -> Vec<u8> {
+  let (Modifiers { alt, ctrl, shift }, name) = Modifiers::strip(key, Modifiers::TMUX);
+
+  // xterm's modifier parameter for special keys
+  let modifier = 1 + usize::from(shift) + 2 * usize::from(alt) + 4 * usize::from(ctrl);
+
+  let csi = |code: &str, end: char| {
+    if modifier == 1 {
+      format!("\x1b[{code}{end}").into_bytes()
+    } else {
+      format!("\x1b[{};{modifier}{end}", if code.is_empty() { "1" } else { code }).into_bytes()
+    }
+  };
+
+  let ss3 = |end: char| {
+    if modifier == 1 {
+      format!("\x1bO{end}").into_bytes()
+    } else {
+      format!("\x1b[1;{modifier}{end}").into_bytes()
+    }
+  };
+
+  let cursor = |end: char| if application_cursor { ss3(end) } else { csi("", end) };
+
+  let bytes = match name {
+    "Up" => return cursor('A'),
+    "Down" => return cursor('B'),
+    "Right" => return cursor('C'),
+    "Left" => return cursor('D'),
+    "Home" => return cursor('H'),
+    "End" => return cursor('F'),
+    "IC" => return csi("2", '~'),
+    "DC" => return csi("3", '~'),
+    "PPage" => return csi("5", '~'),
+    "NPage" => return csi("6", '~'),
+    "BTab" => return b"\x1b[Z".to_vec(),
+    "F1" => return ss3('P'),
+    "F2" => return ss3('Q'),
+    "F3" => return ss3('R'),
+    "F4" => return ss3('S'),
+    "F5" => return csi("15", '~'),
+    "F6" => return csi("17", '~'),
+    "F7" => return csi("18", '~'),
+    "F8" => return csi("19", '~'),
+    "F9" => return csi("20", '~'),
+    "F10" => return csi("21", '~'),
+    "F11" => return csi("23", '~'),
+    "F12" => return csi("24", '~'),
+
+    "Escape" => vec![0x1b],
+    "Enter" => vec![b'\r'],
+    "Tab" => vec![b'\t'],
+    "Space" if ctrl => vec![0],
+    "Space" => vec![b' '],
+    "BSpace" => vec![0x7f],
+
+    name => {
+      let mut chars = name.chars();
+
+      match (chars.next(), chars.next()) {
+        (Some(c), None) if ctrl => match c {
+          ' ' | '@' | '2' => vec![0],
+          '?' | '8' => vec![0x7f],
+          'a'..='z' | 'A'..='Z' | '['..='_' => vec![c.to_ascii_uppercase() as u8 & 0x1f],
+          c => c.to_string().into_bytes(),
+        },
+        (Some(c), None) if shift => c.to_uppercase().to_string().into_bytes(),
+
+        _ => name.as_bytes().to_vec(),
+      }
+    }
+  };
+
+  if alt {
+    [&[0x1b], bytes.as_slice()].concat()
+  } else {
+    bytes
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use encoding_rs::{Encoding, UTF_8};
+
+  use super::{key_bytes, OutputDecoder};
+
+  #[test]
+  fn multibyte_keys() {
+    assert_eq!(key_bytes("€", false), "€".as_bytes());
+    assert_eq!(key_bytes("M-€", false), "\x1b€".as_bytes());
+    assert_eq!(key_bytes("C-é", false), "é".as_bytes());
+    assert_eq!(key_bytes("S-é", false), "É".as_bytes());
+  }
+
+  #[test]
+  fn modifiers() {
+    assert_eq!(key_bytes("C-a", false), [0x01]);
+    assert_eq!(key_bytes("M-C-a", false), [0x1b, 0x01]);
+    assert_eq!(key_bytes("C-Up", false), b"\x1b[1;5A");
+    assert_eq!(key_bytes("Up", true), b"\x1bOA");
+  }
+
+  #[test]
+  fn decode_utf8() {
+    let mut decoder = OutputDecoder::new(UTF_8);
+
+    assert_eq!(decoder.decode(b"a\xc3(b"), "a\u{fffd}(b");
+    assert_eq!(decoder.decode(b"\xff"), "\u{fffd}");
+
+    // split across reads
+    assert_eq!(decoder.decode(b"\xe2\x82"), "");
+    assert_eq!(decoder.decode(b"\xac"), "€");
+  }
+
+  #[test]
+  fn decode_latin1() {
+    let latin1 = Encoding::for_label(b"latin1").unwrap();
+    let mut decoder = OutputDecoder::new(latin1);
+
+    assert_eq!(decoder.decode(b"caf\xe9 \xff"), "café ÿ");
+    assert_eq!(decoder.decode(b"\xc3(b"), "Ã(b");
+
+    // escapes are ASCII, so they pass through untouched even when cut short
+    assert_eq!(decoder.decode(b"\x1b[3"), "\x1b[3");
+    assert_eq!(decoder.decode(b"1m\xe9"), "1mé");
+  }
+}
@@ -0,0 +1,760 @@
+use std::{collections::VecDeque, mem};
+
+use unicode_width::UnicodeWidthChar;
+use vte::{Params, Perform};
+
+use crate::{
+  backend::{Mouse, MouseEncoding, MouseTracking},
+  buffer::Style,
+  geometry::{Point, Size},
+};
+
+/// The number of rows kept once they scroll off the screen, tmux's default `history-limit`.
+const HISTORY_LIMIT: usize = 2000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Cell {
+  /// The cell's grapheme, empty for the second half of a wide character.
+  text: String,
+  /// The style the cell was drawn with, with nothing left unset.
+  style: Style,
+}
+
+impl Cell {
+  fn blank(style: Style) -> Self {
+    Self {
+      text: " ".to_string(),
+      style,
+    }
+  }
+}
+
+/// Which character set `ESC ( <c>` selected.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Charset {
+  Ascii,
+  LineDrawing,
+}
+
+impl Charset {
+  fn map(self, c: char) -> char {
+    if self == Self::Ascii {
+      return c;
+    }
+
+    match c {
+      '`' => '◆',
+      'a' => '▒',
+      'f' => '°',
+      'g' => '±',
+      'j' => '┘',
+      'k' => '┐',
+      'l' => '┌',
+      'm' => '└',
+      'n' => '┼',
+      'o' => '⎺',
+      'p' => '⎻',
+      'q' => '─',
+      'r' => '⎼',
+      's' => '⎽',
+      't' => '├',
+      'u' => '┤',
+      'v' => '┴',
+      'w' => '┬',
+      'x' => '│',
+      'y' => '≤',
+      'z' => '≥',
+      '{' => 'π',
+      '|' => '≠',
+      '}' => '£',
+      '~' => '·',
+      c => c,
+    }
+  }
+}
+
+#[derive(Clone, Copy)]
+struct SavedCursor {
+  x: usize,
+  y: usize,
+  style: Style,
+}
+
+/// The screen of a terminal emulator, driven by a [`vte::Parser`].
+pub struct Screen {
+  size: Size,
+  rows: Vec<Vec<Cell>>,
+  /// The primary screen's rows while the alternate screen is shown.
+  primary: Option<Vec<Vec<Cell>>>,
+  /// Rows scrolled off the top of the primary screen, oldest first.
+  history: VecDeque<Vec<Cell>>,
+
+  x: usize,
+  y: usize,
+  /// Set after printing in the last column, so that the next character wraps.
+  wrap: bool,
+  saved: SavedCursor,
+
+  style: Style,
+  charset: Charset,
+  scroll_top: usize,
+  scroll_bottom: usize,
+
+  pub application_cursor: bool,
+  pub mouse: Mouse,
+  pub bracketed_paste: bool,
+
+  /// Replies to queries such as cursor position reports, to be written back to the program.
+  pub responses: Vec<u8>,
+}
+
+impl Screen {
+  pub fn new(size: Size) -> Self {
+    let size = Self::clamp(size);
+
+    Self {
+      size,
+      rows: vec![vec![Cell::blank(Style::reset()); size.width]; size.height],
+      primary: None,
+      history: VecDeque::new(),
+
+      x: 0,
+      y: 0,
+      wrap: false,
+      saved: SavedCursor {
+        x: 0,
+        y: 0,
+        style: Style::reset(),
+      },
+
+      style: Style::reset(),
+      charset: Charset::Ascii,
+      scroll_top: 0,
+      scroll_bottom: size.height.saturating_sub(1),
+
+      application_cursor: false,
+      mouse: Mouse::default(),
+      bracketed_paste: false,
+
+      responses: Vec::new(),
+    }
+  }
+
+  pub fn size(&self) -> Size {
+    self.size
+  }
+
+  pub fn cursor(&self) -> Point {
+    Point { x: self.x, y: self.y }
+  }
+
+  pub fn resize(&mut self, size: Size) {
+    let size = Self::clamp(size);
+
+    for rows in [Some(&mut self.rows), self.primary.as_mut()].into_iter().flatten() {
+      rows.resize(size.height, vec![Cell::blank(Style::reset()); size.width]);
+
+      for row in rows.iter_mut() {
+        row.resize(size.width, Cell::blank(Style::reset()));
+      }
+    }
+
+    self.size = size;
+    self.x = self.x.min(size.width.saturating_sub(1));
+    self.y = self.y.min(size.height.saturating_sub(1));
+    self.wrap = false;
+    self.scroll_top = 0;
+    self.scroll_bottom = size.height.saturating_sub(1);
+  }
+
+  /// Gives the screen at least one row and column, so that the cursor always has a cell to be on. A window
+  /// only as tall or wide as the popup's padding leaves none.
+  fn clamp(size: Size) -> Size {
+    Size {
+      height: size.height.max(1),
+      width: size.width.max(1),
+    }
+  }
+
+  pub fn history_size(&self) -> usize {
+    self.history.len()
+  }
+
+  /// Renders the screen, `scroll` lines back into the history, the way `tmux capture-pane -p -e` does.
+  pub fn capture(&self, scroll: usize) -> Vec<u8> {
+    let rows = self
+      .history
+      .iter()
+      .chain(&self.rows)
+      .skip(self.history.len() - scroll.min(self.history.len()))
+      .take(self.size.height);
+
+    render(rows)
+  }
+
+  /// Renders the whole history followed by the screen.
+  pub fn capture_history(&self) -> Vec<u8> {
+    render(self.history.iter().chain(&self.rows))
+  }
+
+  fn blank(&self) -> Cell {
+    Cell::blank(self.style.erased())
+  }
+
+  fn blank_row(&self) -> Vec<Cell> {
+    vec![self.blank(); self.size.width]
+  }
+
+  fn scroll_up(&mut self, count: usize) {
+    // only whole-screen scrolls of the primary screen keep history, as tmux does
+    let keep = self.scroll_top == 0 && self.primary.is_none();
+
+    for _ in 0..count.min(self.scroll_bottom + 1 - self.scroll_top) {
+      let row = self.rows.remove(self.scroll_top);
+      self.rows.insert(self.scroll_bottom, self.blank_row());
+
+      if keep {
+        if self.history.len() == HISTORY_LIMIT {
+          self.history.pop_front();
+        }
+
+        self.history.push_back(row);
+      }
+    }
+  }
+
+  fn scroll_down(&mut self, count: usize) {
+    for _ in 0..count.min(self.scroll_bottom + 1 - self.scroll_top) {
+      self.rows.remove(self.scroll_bottom);
+      self.rows.insert(self.scroll_top, self.blank_row());
+    }
+  }
+
+  fn line_feed(&mut self) {
+    if self.y == self.scroll_bottom {
+      self.scroll_up(1);
+    } else if self.y + 1 < self.size.height {
+      self.y += 1;
+    }
+  }
+
+  fn reverse_line_feed(&mut self) {
+    if self.y == self.scroll_top {
+      self.scroll_down(1);
+    } else {
+      self.y = self.y.saturating_sub(1);
+    }
+  }
+
+  fn move_to(&mut self, x: usize, y: usize) {
+    self.x = x.min(self.size.width.saturating_sub(1));
+    self.y = y.min(self.size.height.saturating_sub(1));
+    self.wrap = false;
+  }
+
+  /// Moves the cursor up, stopping at the top of the scroll region if it started within it.
+  fn cursor_up(&mut self, count: usize) {
+    let top = if self.y >= self.scroll_top { self.scroll_top } else { 0 };
+
+    self.move_to(self.x, self.y.saturating_sub(count).max(top));
+  }
+
+  fn cursor_down(&mut self, count: usize) {
+    let bottom = if self.y <= self.scroll_bottom {
+      self.scroll_bottom
+    } else {
+      self.size.height - 1
+    };
+
+    self.move_to(self.x, (self.y + count).min(bottom));
+  }
+
+  fn erase(&mut self, y: usize, xs: impl Iterator<Item = usize>) {
+    let blank = self.blank();
+
+    for x in xs {
+      self.rows[y][x] = blank.clone();
+    }
+  }
+
+  fn erase_display(&mut self, mode: u16) {
+    let (width, height) = (self.size.width, self.size.height);
+
+    match mode {
+      0 => {
+        self.erase(self.y, self.x..width);
+        (self.y + 1..height).for_each(|y| self.erase(y, 0..width));
+      }
+      1 => {
+        (0..self.y).for_each(|y| self.erase(y, 0..width));
+        self.erase(self.y, 0..(self.x + 1).min(width));
+      }
+      2 => (0..height).for_each(|y| self.erase(y, 0..width)),
+      // clears the scrollback rather than the screen, as in xterm
+      3 => self.history.clear(),
+
+      _ => (),
+    }
+  }
+
+  fn erase_line(&mut self, mode: u16) {
+    let width = self.size.width;
+
+    match mode {
+      0 => self.erase(self.y, self.x..width),
+      1 => self.erase(self.y, 0..(self.x + 1).min(width)),
+      2 => self.erase(self.y, 0..width),
+
+      _ => (),
+    }
+  }
+
+  fn insert_lines(&mut self, count: usize) {
+    if !(self.scroll_top..=self.scroll_bottom).contains(&self.y) {
+      return;
+    }
+
+    for _ in 0..count.min(self.scroll_bottom + 1 - self.y) {
+      self.rows.remove(self.scroll_bottom);
+      self.rows.insert(self.y, self.blank_row());
+    }
+  }
+
+  fn delete_lines(&mut self, count: usize) {
+    if !(self.scroll_top..=self.scroll_bottom).contains(&self.y) {
+      return;
+    }
+
+    for _ in 0..count.min(self.scroll_bottom + 1 - self.y) {
+      self.rows.remove(self.y);
+      self.rows.insert(self.scroll_bottom, self.blank_row());
+    }
+  }
+
+  fn insert_chars(&mut self, count: usize) {
+    let blank = self.blank();
+    let row = &mut self.rows[self.y];
+
+    for _ in 0..count.min(row.len() - self.x) {
+      row.pop();
+      row.insert(self.x, blank.clone());
+    }
+  }
+
+  fn delete_chars(&mut self, count: usize) {
+    let blank = self.blank();
+    let row = &mut self.rows[self.y];
+
+    for _ in 0..count.min(row.len() - self.x) {
+      row.remove(self.x);
+      row.push(blank.clone());
+    }
+  }
+
+  fn set_alternate_screen(&mut self, on: bool) {
+    match (on, self.primary.is_some()) {
+      (true, false) => {
+        let alternate = vec![vec![Cell::blank(Style::reset()); self.size.width]; self.size.height];
+        self.primary = Some(mem::replace(&mut self.rows, alternate));
+      }
+      (false, true) => self.rows = self.primary.take().unwrap_or_default(),
+
+      _ => (),
+    }
+  }
+
+  fn save_cursor(&mut self) {
+    self.saved = SavedCursor {
+      x: self.x,
+      y: self.y,
+      style: self.style,
+    };
+  }
+
+  fn restore_cursor(&mut self) {
+    let SavedCursor { x, y, style } = self.saved;
+
+    self.style = style;
+    self.move_to(x, y);
+  }
+
+  fn set_mode(&mut self, mode: u16, private: bool, on: bool) {
+    if !private {
+      return;
+    }
+
+    match mode {
+      1 => self.application_cursor = on,
+      9 | 1000 | 1002 | 1003 if on => {
+        self.mouse.tracking = match mode {
+          9 => MouseTracking::X10,
+          1000 => MouseTracking::Normal,
+          1002 => MouseTracking::Button,
+          _ => MouseTracking::Any,
+        }
+      }
+      9 | 1000 | 1002 | 1003 => self.mouse.tracking = MouseTracking::None,
+      1005 | 1006 | 1015 if on => {
+        self.mouse.encoding = match mode {
+          1005 => MouseEncoding::Utf8,
+          1006 => MouseEncoding::Sgr,
+          _ => MouseEncoding::Urxvt,
+        }
+      }
+      1005 | 1006 | 1015 => self.mouse.encoding = MouseEncoding::Default,
+      2004 => self.bracketed_paste = on,
+      47 | 1047 => self.set_alternate_screen(on),
+      1048 if on => self.save_cursor(),
+      1048 => self.restore_cursor(),
+      1049 if on => {
+        self.save_cursor();
+        self.set_alternate_screen(true);
+        self.erase_display(2);
+      }
+      1049 => {
+        self.set_alternate_screen(false);
+        self.restore_cursor();
+      }
+
+      _ => (),
+    }
+  }
+
+  fn set_graphics(&mut self, params: &Params) {
+    self.style.merge(&Style::from_sgr(params));
+  }
+}
+
+impl Perform for Screen {
+  fn print(&mut self, c: char) {
+    let c = self.charset.map(c);
+    let width = c.width().unwrap_or(0);
+
+    if width == 0 {
+      // combining characters join the previously printed cell
+      let x = if self.wrap { self.x } else { self.x.saturating_sub(1) };
+      if let Some(cell) = self.rows[self.y][..=x]
+        .iter_mut()
+        .rev()
+        .find(|cell| !cell.text.is_empty())
+      {
+        cell.text.push(c);
+      }
+
+      return;
+    }
+
+    if self.wrap || self.x + width > self.size.width {
+      self.x = 0;
+      self.wrap = false;
+      self.line_feed();
+    }
+
+    if width > self.size.width {
+      return;
+    }
+
+    let style = self.style;
+    let row = &mut self.rows[self.y];
+
+    row[self.x] = Cell {
+      text: c.to_string(),
+      style,
+    };
+    if width == 2 {
+      row[self.x + 1] = Cell {
+        text: String::new(),
+        style,
+      };
+    }
+
+    if self.x + width == self.size.width {
+      self.x = self.size.width - 1;
+      self.wrap = true;
+    } else {
+      self.x += width;
+    }
+  }
+
+  fn execute(&mut self, byte: u8) {
+    match byte {
+      b'\x08' => self.move_to(self.x.saturating_sub(1), self.y),
+      b'\t' => self.move_to((self.x / 8 + 1) * 8, self.y),
+      b'\n' | b'\x0b' | b'\x0c' => {
+        self.wrap = false;
+        self.line_feed();
+      }
+      b'\r' => self.move_to(0, self.y),
+      b'\x0e' => self.charset = Charset::LineDrawing,
+      b'\x0f' => self.charset = Charset::Ascii,
+
+      _ => (),
+    }
+  }
+
+  fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+    if ignore {
+      return;
+    }
+
+    let args: Vec<u16> = params.iter().map(|param| param[0]).collect();
+    // most sequences treat a missing or zero argument as one
+    let arg = |i: usize| args.get(i).copied().filter(|&n| n != 0).unwrap_or(1) as usize;
+    let private = intermediates == b"?";
+
+    match action {
+      '@' => self.insert_chars(arg(0)),
+      'A' => self.cursor_up(arg(0)),
+      'B' | 'e' => self.cursor_down(arg(0)),
+      'C' | 'a' => self.move_to(self.x + arg(0), self.y),
+      'D' => self.move_to(self.x.saturating_sub(arg(0)), self.y),
+      'E' => self.move_to(0, self.y + arg(0)),
+      'F' => self.move_to(0, self.y.saturating_sub(arg(0))),
+      'G' | '`' => self.move_to(arg(0) - 1, self.y),
+      'H' | 'f' => self.move_to(arg(1) - 1, arg(0) - 1),
+      'J' => self.erase_display(args.first().copied().unwrap_or(0)),
+      'K' => self.erase_line(args.first().copied().unwrap_or(0)),
+      'L' => self.insert_lines(arg(0)),
+      'M' => self.delete_lines(arg(0)),
+      'P' => self.delete_chars(arg(0)),
+      'S' => self.scroll_up(arg(0)),
+      'T' => self.scroll_down(arg(0)),
+      'X' => {
+        let end = (self.x + arg(0)).min(self.size.width);
+        self.erase(self.y, self.x..end);
+      }
+      'd' => self.move_to(self.x, arg(0) - 1),
+      'm' if intermediates.is_empty() => self.set_graphics(params),
+      'h' => args.iter().for_each(|&mode| self.set_mode(mode, private, true)),
+      'l' => args.iter().for_each(|&mode| self.set_mode(mode, private, false)),
+      'r' if intermediates.is_empty() => {
+        let top = arg(0) - 1;
+        let bottom = args
+          .get(1)
+          .copied()
+          .filter(|&n| n != 0)
+          .map_or(self.size.height, usize::from)
+          - 1;
+
+        if top < bottom && bottom < self.size.height {
+          self.scroll_top = top;
+          self.scroll_bottom = bottom;
+          self.move_to(0, 0);
+        }
+      }
+      's' if intermediates.is_empty() => self.save_cursor(),
+      'u' if intermediates.is_empty() => self.restore_cursor(),
+      'n' if intermediates.is_empty() => match args.first() {
+        Some(5) => self.responses.extend_from_slice(b"\x1b[0n"),
+        Some(6) => self
+          .responses
+          .extend_from_slice(format!("\x1b[{};{}R", self.y + 1, self.x + 1).as_bytes()),
+
+        _ => (),
+      },
+      'c' if intermediates.is_empty() => self.responses.extend_from_slice(b"\x1b[?62;22c"),
+      'c' if intermediates == b">" => self.responses.extend_from_slice(b"\x1b[>0;0;0c"),
+
+      _ => (),
+    }
+  }
+
+  fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
+    if ignore {
+      return;
+    }
+
+    match (intermediates, byte) {
+      ([], b'7') => self.save_cursor(),
+      ([], b'8') => self.restore_cursor(),
+      ([], b'D') => self.line_feed(),
+      ([], b'E') => {
+        self.move_to(0, self.y);
+        self.line_feed();
+      }
+      ([], b'M') => self.reverse_line_feed(),
+      ([], b'c') => *self = Self::new(self.size),
+      ([b'('], b'0') => self.charset = Charset::LineDrawing,
+      ([b'('], _) => self.charset = Charset::Ascii,
+
+      _ => (),
+    }
+  }
+}
+
+/// Renders `rows` the way `tmux capture-pane -p -e` does.
+fn render<'a>(rows: impl Iterator<Item = &'a Vec<Cell>>) -> Vec<u8> {
+  let mut out = String::new();
+  let mut style = Style::reset();
+
+  for row in rows {
+    // trailing blanks are trimmed, as tmux does
+    let len = row
+      .iter()
+      .rposition(|cell| cell.text != " " || cell.style != Style::reset())
+      .map_or(0, |i| i + 1);
+
+    for cell in &row[..len] {
+      if cell.style != style {
+        style = cell.style;
+        out.push_str(&style.sgr());
+      }
+
+      out.push_str(&cell.text);
+    }
+
+    out.push('\n');
+  }
+
+  out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+  use vte::Parser;
+
+  use super::{Screen, HISTORY_LIMIT};
+  use crate::geometry::Size;
+
+  /// A screen of `height` rows and `width` columns that `input` has been written to.
+  fn screen(height: usize, width: usize, input: &str) -> Screen {
+    let mut screen = Screen::new(Size { height, width });
+    Parser::new().advance(&mut screen, input.as_bytes());
+
+    screen
+  }
+
+  fn capture(screen: &Screen, scroll: usize) -> String {
+    String::from_utf8(screen.capture(scroll)).unwrap()
+  }
+
+  #[test]
+  fn wrapping() {
+    assert_eq!(capture(&screen(3, 5, "abcdefg"), 0), "abcde\nfg\n\n");
+    // a newline after the last column doesn't leave an empty line
+    assert_eq!(capture(&screen(3, 5, "abcde\r\nx"), 0), "abcde\nx\n\n");
+    // a wide character that doesn't fit moves to the next line
+    assert_eq!(capture(&screen(2, 5, "abcd本"), 0), "abcd\n本\n");
+  }
+
+  #[test]
+  fn scroll_regions() {
+    let lines = "1\r\n2\r\n3\r\n4\r\n5\x1b[2;4r";
+
+    for (input, expected) in [
+      ("\x1b[S", "1\n3\n4\n\n5\n"),
+      ("\x1b[T", "1\n\n2\n3\n5\n"),
+      ("\x1b[3H\x1b[L", "1\n2\n\n3\n5\n"),
+      ("\x1b[3H\x1b[M", "1\n2\n4\n\n5\n"),
+      // line feeds at the bottom of the region scroll only the region
+      ("\x1b[4H\n", "1\n3\n4\n\n5\n"),
+    ] {
+      let screen = screen(5, 3, &format!("{lines}{input}"));
+
+      assert_eq!(capture(&screen, 0), expected, "{input:?}");
+      assert_eq!(screen.history_size(), 0, "{input:?}");
+    }
+  }
+
+  #[test]
+  fn alternate_screen() {
+    let mut screen = screen(2, 5, "a\r\nb\r\nc");
+    assert_eq!(screen.history_size(), 1);
+
+    Parser::new().advance(&mut screen, b"\x1b[?1049hx\r\ny\r\nz\r\n");
+    assert_eq!(capture(&screen, 0), "z\n\n");
+    assert_eq!(screen.history_size(), 1);
+
+    Parser::new().advance(&mut screen, b"\x1b[?1049l");
+    assert_eq!(capture(&screen, 0), "b\nc\n");
+    assert_eq!(capture(&screen, 1), "a\nb\n");
+  }
+
+  #[test]
+  fn history() {
+    let lines: Vec<_> = (0..10).map(|n| n.to_string()).collect();
+    let screen = screen(2, 5, &lines.join("\r\n"));
+
+    assert_eq!(screen.history_size(), 8);
+    assert_eq!(capture(&screen, 0), "8\n9\n");
+    assert_eq!(capture(&screen, 3), "5\n6\n");
+    assert_eq!(capture(&screen, 100), "0\n1\n");
+    assert_eq!(
+      String::from_utf8(screen.capture_history()).unwrap(),
+      lines.join("\n") + "\n"
+    );
+  }
+
+  #[test]
+  fn history_limit() {
+    let lines: String = (0..HISTORY_LIMIT + 100).map(|n| format!("{n}\r\n")).collect();
+    let screen = screen(1, 5, &lines);
+
+    assert_eq!(screen.history_size(), HISTORY_LIMIT);
+    assert_eq!(capture(&screen, 1), format!("{}\n", HISTORY_LIMIT + 99));
+    assert_eq!(capture(&screen, usize::MAX), "100\n");
+  }
+
+  #[test]
+  fn erase() {
+    let text = "abc\r\ndef\r\nghi\x1b[2;2H";
+
+    for (input, expected) in [
+      ("\x1b[J", "abc\nd\n\n"),
+      ("\x1b[1J", "\n  f\nghi\n"),
+      ("\x1b[2J", "\n\n\n"),
+      ("\x1b[K", "abc\nd\nghi\n"),
+      ("\x1b[1K", "abc\n  f\nghi\n"),
+      ("\x1b[2K", "abc\n\nghi\n"),
+      ("\x1b[X", "abc\nd f\nghi\n"),
+    ] {
+      assert_eq!(
+        capture(&screen(3, 3, &format!("{text}{input}")), 0),
+        expected,
+        "{input:?}"
+      );
+    }
+
+    // 3 clears the history, and leaves the screen alone
+    let screen = screen(2, 3, "a\r\nb\r\nc\x1b[3J");
+    assert_eq!(screen.history_size(), 0);
+    assert_eq!(capture(&screen, 0), "b\nc\n");
+  }
+
+  #[test]
+  fn responses() {
+    for (input, expected) in [
+      ("\x1b[5n", "\x1b[0n"),
+      ("\x1b[2;3H\x1b[6n", "\x1b[2;3R"),
+      ("\x1b[c", "\x1b[?62;22c"),
+      ("\x1b[>c", "\x1b[>0;0;0c"),
+    ] {
+      assert_eq!(screen(3, 5, input).responses, expected.as_bytes(), "{input:?}");
+    }
+  }
+
+  #[test]
+  fn styles() {
+    assert_eq!(
+      capture(&screen(1, 5, "\x1b[1;38;5;196mA\x1b[0mB"), 0),
+      "\x1b[0;1;38;2;255;0;0mA\x1b[0mB\n"
+    );
+    assert_eq!(
+      capture(&screen(1, 5, "\x1b[4:3;58;5;1;53mA"), 0),
+      "\x1b[0;53;4:3;58;5;1mA\n"
+    );
+    // out of range, so left unset rather than wrapped
+    assert_eq!(capture(&screen(1, 5, "\x1b[38;5;300mA"), 0), "A\n");
+    // erased cells keep the background
+    assert_eq!(capture(&screen(1, 3, "\x1b[41m\x1b[2K"), 0), "\x1b[0;41m   \n");
+  }
+
+  #[test]
+  fn empty_screen() {
+    let mut screen = screen(0, 10, "hello\r\nworld\x1b[B\x1b[r\x1b[L\x1b[S\u{301}");
+    screen.resize(Size { height: 0, width: 0 });
+    Parser::new().advance(&mut screen, "hello\r\n本\x1b[B\x1b[r\x1b[@".as_bytes());
+
+    assert_eq!(screen.size(), Size { height: 1, width: 1 });
+  }
+}
@@ -1,69 +1,451 @@
-use std::{str::FromStr, sync::mpsc::Sender};
+use std::{
+  fs::File,
+  io::{BufRead, BufReader},
+  str::FromStr,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::Sender,
+    Arc,
+  },
+};
 
 use anyhow::Result;
+use parking_lot::Mutex;
+use strum::{Display, EnumString};
 
 use super::{Spawn, Step};
 use crate::{
+  backend::{Backend, Event as RefreshEvent, Key as TmuxKey, Modifiers, Mouse, MouseTracking},
   fifo::Fifo,
   geometry::Point,
   kakoune::Kakoune,
-  tmux::{Key as TmuxKey, Tmux},
+  popup::Settings,
+  view::{Selection, View},
+  yank::{self, Extent, Target},
 };
 
+/// What a key bound in the [`Keymap`] does, instead of being sent to the terminal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum Action {
+  /// Closes the popup, killing its command.
+  Quit,
+  /// Closes the popup, leaving its command running to be re-opened later. Only tmux popups can be
+  /// detached, elsewhere the key is sent as usual.
+  Detach,
+  /// Toggles scroll mode.
+  Scroll,
+  /// Toggles between the configured padding and filling most of the window.
+  Zoom,
+  /// Sends the next key to the terminal as it is, even if it's bound to an action.
+  Literal,
+  /// Pastes the `"` register, see `popup-paste`.
+  Paste,
+}
+
+/// The keys bound to popup actions.
+#[derive(Clone)]
+pub struct Keymap {
+  bindings: Vec<(Action, String)>,
+}
+
+impl Default for Keymap {
+  fn default() -> Self {
+    Self {
+      bindings: vec![
+        (Action::Quit, "<c-space>".to_string()),
+        (Action::Detach, "<a-space>".to_string()),
+        (Action::Scroll, "<a-s>".to_string()),
+        (Action::Zoom, "<a-z>".to_string()),
+        (Action::Literal, "<a-v>".to_string()),
+        (Action::Paste, "<a-p>".to_string()),
+      ],
+    }
+  }
+}
+
+impl Keymap {
+  /// Applies whitespace-separated `action=key` pairs, where an empty key unbinds the action. A key
+  /// only ever performs one action, so binding it takes it from any other.
+  pub fn bind(&mut self, pairs: &str) -> Result<()> {
+    for pair in pairs.split_whitespace() {
+      let (action, key) = pair
+        .split_once('=')
+        .ok_or(anyhow::anyhow!("invalid key binding {pair:?}, expected ACTION=KEY"))?;
+      let action: Action = action
+        .parse()
+        .map_err(|_| anyhow::anyhow!("unknown popup action {action:?}"))?;
+
+      self
+        .bindings
+        .retain(|(bound, bound_key)| *bound != action && bound_key != key);
+
+      if !key.is_empty() {
+        self.bindings.push((action, key.to_string()));
+      }
+    }
+
+    Ok(())
+  }
+
+  pub fn action(&self, key: &str) -> Option<Action> {
+    self
+      .bindings
+      .iter()
+      .find_map(|(action, bound)| (bound == key).then_some(*action))
+  }
+}
+
 pub struct Keys {
-  padding: usize,
-  tmux: Tmux,
-  keys_fifo: Fifo,
-  commands_fifo: Fifo,
-  refresh: Sender<()>,
+  kakoune: Kakoune,
+  keymap: Keymap,
+  backend: Arc<dyn Backend>,
+  /// Kakoune writes each key on a line of its own as soon as it's pressed, without waiting for the
+  /// previous one to be handled.
+  keys: Mutex<BufReader<File>>,
+  refresh: Sender<RefreshEvent>,
+  view: View,
+  /// The mouse button last pressed and not yet released, which kakoune leaves out of motion events.
+  held: Mutex<Option<MouseButton>>,
+  /// Set by [`Action::Literal`], so that the next key goes to the terminal.
+  literal: AtomicBool,
 }
 
 impl Keys {
-  pub const QUIT_KEY: &'static str = "<c-space>";
   const CAPTURE_KEYS: &'static str = "popup-capture-keys";
+  const PASTE: &'static str = "popup-paste";
 
   pub fn new(
-    kakoune: &Kakoune,
-    padding: usize,
-    tmux: Tmux,
+    kakoune: Kakoune,
+    settings: &Settings,
+    backend: Arc<dyn Backend>,
     keys_fifo: Fifo,
-    commands_fifo: Fifo,
-    refresh: Sender<()>,
+    refresh: Sender<RefreshEvent>,
+    view: View,
   ) -> Result<Self> {
+    // opened before capturing keys, so that kakoune never waits on the fifo
+    let keys = Mutex::new(keys_fifo.reader()?);
     kakoune.eval(Self::CAPTURE_KEYS)?;
 
     Ok(Self {
-      padding,
-      tmux,
-      keys_fifo,
-      commands_fifo,
+      kakoune,
+      keymap: settings.keymap.clone(),
+      backend,
+      keys,
       refresh,
+      view,
+      held: Mutex::new(None),
+      literal: AtomicBool::new(false),
     })
   }
+
+  /// Moves the view while in scroll mode, where keys are handled by the popup instead of the terminal.
+  fn scroll(&self, scroll: usize, key: &str) -> Result<()> {
+    let info = self.backend.display_info()?;
+    let page = info.size.height;
+
+    let scroll = match key {
+      "q" | "<esc>" => None,
+
+      // like leaving copy mode in tmux after copying
+      "y" => {
+        self.yank(Extent::Screen { scroll }, &Target::Register("dquote".to_string()))?;
+        None
+      }
+      "Y" => {
+        self.yank(Extent::History, &Target::Buffer)?;
+        None
+      }
+
+      "k" | "<up>" => Some(scroll + 1),
+      "j" | "<down>" => Some(scroll.saturating_sub(1)),
+      "<c-u>" => Some(scroll + page / 2),
+      "<c-d>" => Some(scroll.saturating_sub(page / 2)),
+      "<c-b>" | "<pageup>" => Some(scroll + page),
+      "<c-f>" | "<pagedown>" => Some(scroll.saturating_sub(page)),
+      "g" | "<home>" => Some(info.history_size),
+      "G" | "<end>" => Some(0),
+
+      key => match Key::try_from(key).map(|key| key.event) {
+        // negative amounts scroll up, towards the start of the history
+        Ok(Event::Scroll {
+          amount,
+          horizontal: false,
+          ..
+        }) if amount < 0 => Some(scroll + amount.unsigned_abs() as usize),
+        Ok(Event::Scroll {
+          amount,
+          horizontal: false,
+          ..
+        }) => Some(scroll.saturating_sub(amount as usize)),
+
+        _ => Some(scroll),
+      },
+    };
+
+    self.view.set_scroll(scroll.map(|scroll| scroll.min(info.history_size)));
+
+    Ok(())
+  }
+
+  /// Selects text with the mouse while the program isn't tracking it, or in scroll mode, copying it into
+  /// the `"` register once the button is released. Returns whether `key` was handled, clearing the
+  /// selection for any key that isn't part of one.
+  fn select(&self, key: &str) -> Result<bool> {
+    let Ok(mut key) = Key::try_from(key) else {
+      self.view.set_selection(None);
+      return Ok(false);
+    };
+
+    key.unpad_coords(self.view.padding());
+
+    let Event::Mouse { button, action, coords } = key.event else {
+      self.view.set_selection(None);
+      return Ok(false);
+    };
+
+    let scroll = self.view.scroll();
+
+    if scroll.is_none() && self.backend.display_info()?.mouse.tracking != MouseTracking::None {
+      return Ok(false);
+    }
+
+    // unpadded coordinates count from 1, as terminals report them
+    let coords = Point {
+      x: coords.x - 1,
+      y: coords.y - 1,
+    };
+    let dragged = self.view.selection().filter(|selection| selection.dragging);
+
+    match (action, button, dragged) {
+      (MouseAction::Press, Some(MouseButton::Left), _) => self.view.set_selection(Some(Selection {
+        start: coords,
+        end: coords,
+        dragging: true,
+      })),
+
+      (MouseAction::Move, _, Some(selection)) => self.view.set_selection(Some(Selection {
+        end: coords,
+        ..selection
+      })),
+
+      (MouseAction::Release, Some(MouseButton::Left), Some(selection)) => {
+        let selection = Selection {
+          end: coords,
+          dragging: false,
+          ..selection
+        };
+
+        // a click without a drag selects nothing
+        if selection.start == selection.end {
+          self.view.set_selection(None);
+        } else {
+          self.view.set_selection(Some(selection));
+          self.yank(
+            Extent::Selection {
+              scroll: scroll.unwrap_or_default(),
+              selection,
+            },
+            &Target::Register("dquote".to_string()),
+          )?;
+        }
+      }
+
+      _ => (),
+    }
+
+    Ok(true)
+  }
+
+  /// Translates `key` for the terminal, queuing it onto `pending`.
+  fn send(&self, key: &str, pending: &mut Vec<TmuxKey>) -> Result<()> {
+    let mut key = Key::try_from(key)?;
+    key.unpad_coords(self.view.padding());
+    key.track_buttons(&mut self.held.lock());
+
+    // only mouse events depend on the modes the program has enabled
+    let mouse = match key.event {
+      Event::Named(_) => Mouse::default(),
+      _ => self.backend.display_info()?.mouse,
+    };
+
+    pending.extend(key.translate(mouse));
+
+    Ok(())
+  }
+
+  /// Sends the keys queued so far to the terminal.
+  fn flush(&self, pending: &mut Vec<TmuxKey>) -> Result<()> {
+    if !pending.is_empty() {
+      self.backend.send_keys(std::mem::take(pending))?;
+    }
+
+    Ok(())
+  }
+
+  /// Waits for a key, then takes whichever others have been pressed since without waiting again.
+  fn read(&self) -> Result<Vec<String>> {
+    let mut reader = self.keys.lock();
+    let mut keys = Vec::new();
+
+    loop {
+      let mut line = String::new();
+      anyhow::ensure!(reader.read_line(&mut line)? > 0, "keys fifo closed");
+
+      let key = line.trim();
+      if !key.is_empty() {
+        keys.push(key.to_string());
+      }
+
+      if reader.buffer().is_empty() {
+        return Ok(keys);
+      }
+    }
+  }
+
+  /// Handles a single key, queuing it onto `pending` if it's for the terminal. The queue is flushed before
+  /// any action, so keys and actions take effect in the order they were pressed.
+  fn handle(&self, key: &str, pending: &mut Vec<TmuxKey>) -> Result<Step> {
+    let action = if self.literal.swap(false, Ordering::SeqCst) {
+      None
+    } else {
+      self.keymap.action(key)
+    };
+
+    if action.is_some() {
+      self.flush(pending)?;
+    }
+
+    match action {
+      Some(Action::Quit) => return Ok(Step::Quit),
+      // only tmux sessions outlive this process
+      Some(Action::Detach) if self.backend.as_tmux().is_some() => return Ok(Step::Detach),
+
+      Some(Action::Scroll) => {
+        let scroll = self.view.scroll();
+        self.view.set_scroll(if scroll.is_some() { None } else { Some(0) });
+        self.view.set_selection(None);
+      }
+      Some(Action::Zoom) => {
+        self.view.toggle_zoom();
+        self.view.set_selection(None);
+        self.backend.set_size(self.view.size()?)?;
+      }
+      Some(Action::Literal) => self.literal.store(true, Ordering::SeqCst),
+      // kakoune has the register, so it's asked to write it to the paste fifo
+      Some(Action::Paste) => self.kakoune.eval(Self::PASTE)?,
+
+      _ if self.select(key)? => (),
+      _ => match self.view.scroll() {
+        Some(scroll) => self.scroll(scroll, key)?,
+        None => self.send(key, pending)?,
+      },
+    }
+
+    Ok(Step::Next)
+  }
+
+  fn yank(&self, extent: Extent, target: &Target) -> Result<()> {
+    yank::yank(&self.kakoune, &*self.backend, &self.view.title(), extent, target)
+  }
 }
 
 impl Spawn for Keys {
   const NAME: &'static str = "keys";
 
   fn step(&self) -> Result<Step> {
-    let key = self.keys_fifo.read()?;
-    let key = key.trim();
+    let mut pending = Vec::new();
 
-    if key == Self::QUIT_KEY {
-      return Ok(Step::Quit);
+    for key in self.read()? {
+      match self.handle(&key, &mut pending)? {
+        Step::Next => (),
+        step => return Ok(step),
+      }
     }
 
-    let mut key = Key::try_from(key)?;
-    key.unpad_coords(self.padding);
-
-    self.tmux.send_keys(key.into())?;
-    self.commands_fifo.write(Self::CAPTURE_KEYS)?;
-    self.refresh.send(())?;
+    self.flush(&mut pending)?;
+    self.refresh.send(RefreshEvent::Input)?;
 
     Ok(Step::Next)
   }
 }
 
+/// Kakoune's names for keys, other than those it names by the character they type, along with tmux's
+/// names for them. Function keys are handled separately.
+const KEY_NAMES: &[(&str, &str)] = &[
+  ("ret", "Enter"),
+  ("space", "Space"),
+  ("tab", "Tab"),
+  ("lt", "<"),
+  ("gt", ">"),
+  ("backspace", "BSpace"),
+  ("esc", "Escape"),
+  ("up", "Up"),
+  ("down", "Down"),
+  ("left", "Left"),
+  ("right", "Right"),
+  ("pageup", "PPage"),
+  ("pagedown", "NPage"),
+  ("home", "Home"),
+  ("end", "End"),
+  ("ins", "IC"),
+  ("del", "DC"),
+  ("plus", "+"),
+  ("minus", "-"),
+  ("semicolon", ";"),
+  ("percent", "%"),
+  ("quote", "'"),
+  ("dquote", "\""),
+];
+
+/// The name tmux's `send-keys` knows the kakoune key `name` by, with `modifiers` applied, or `None` for
+/// keys that aren't typed into the terminal.
+fn tmux_key_name(name: &str, modifiers: &Modifiers) -> Option<String> {
+  // kakoune reports focus changes as keys, which programs have to ask for separately
+  if matches!(name, "focus_in" | "focus_out") {
+    return None;
+  }
+
+  let mut shift = modifiers.shift;
+  let function_key = name
+    .strip_prefix(['f', 'F'])
+    .and_then(|number| number.parse::<u8>().ok())
+    .filter(|number| (1..=12).contains(number));
+
+  let mut key = if let Some(number) = function_key {
+    format!("F{number}")
+  } else if let Some((_, tmux_name)) = KEY_NAMES.iter().find(|(kak_name, _)| *kak_name == name) {
+    tmux_name.to_string()
+  } else {
+    name.to_string()
+  };
+
+  // tmux names shift-tab separately, and types shifted characters as themselves rather than with S-
+  if key == "Tab" && shift {
+    key = "BTab".to_string();
+    shift = false;
+  } else if shift && key.chars().count() == 1 {
+    key = key.to_uppercase();
+    shift = false;
+  }
+
+  let mut tmux_key = String::new();
+
+  if modifiers.alt {
+    tmux_key.push_str("M-");
+  }
+  if modifiers.ctrl {
+    tmux_key.push_str("C-");
+  }
+  if shift {
+    tmux_key.push_str("S-");
+  }
+
+  tmux_key.push_str(&key);
+
+  Some(tmux_key)
+}
+
 struct Key<'a> {
   event: Event<'a>,
   modifiers: Modifiers,
@@ -84,78 +466,75 @@ impl<'a> Key<'a> {
       _ => (),
     }
   }
-}
 
-impl<'a> From<Key<'a>> for TmuxKey {
-  fn from(key: Key<'a>) -> Self {
-    let Key { event, modifiers } = key;
+  /// Fills in the `held` button of motion events, so that drags are reported as such, and keeps track of
+  /// it as buttons are pressed and released.
+  fn track_buttons(&mut self, held: &mut Option<MouseButton>) {
+    let Event::Mouse {
+      ref mut button, action, ..
+    } = self.event
+    else {
+      return;
+    };
+
+    match action {
+      MouseAction::Press => *held = *button,
+      MouseAction::Release => *held = None,
+      MouseAction::Move => *button = button.or(*held),
+    }
+  }
 
-    match event {
-      Event::Scroll { amount, coords } => {
-        let up = amount > 0;
-        // 64 is the scroll
-        if let Some(Point { x: column, y: line }) = coords {
-          let modifiers = 64 | usize::from(modifiers) | usize::from(up);
+  /// Translates the key for the terminal. Mouse events are reported as `mouse` asks for, and wheel
+  /// events fall back to arrow keys for programs that don't use the mouse, while any other mouse events
+  /// they haven't asked for are dropped.
+  fn translate(self, mouse: Mouse) -> Option<TmuxKey> {
+    let Key { event, modifiers } = self;
 
-          TmuxKey::Esc(format!("<{modifiers};{column};{line}M"))
-        } else if up {
-          TmuxKey::Key("Up".to_string())
+    let key = match event {
+      Event::Scroll {
+        amount,
+        coords,
+        horizontal,
+      } => {
+        // kakoune's positive amounts scroll down, or right
+        let forward = amount > 0;
+
+        // wheel events are buttons 4 to 7: up, down, left and right
+        let button = 64 | usize::from(modifiers) | (2 * usize::from(horizontal)) | usize::from(forward);
+        let report = coords.and_then(|Point { x: column, y: line }| mouse.report(button, column, line, false));
+
+        if let Some(report) = report {
+          TmuxKey::Bytes(report)
         } else {
-          TmuxKey::Key("Down".to_string())
+          TmuxKey::Key(
+            match (horizontal, forward) {
+              (false, false) => "Up",
+              (false, true) => "Down",
+              (true, false) => "Left",
+              (true, true) => "Right",
+            }
+            .to_string(),
+          )
         }
       }
 
       Event::Mouse { button, action, coords } => {
-        let modifiers = usize::from(modifiers) | button.map_or(0, usize::from) | usize::from(action);
+        // motion without a button held is reported as the "release" button, 3
+        let button = match (action, button) {
+          (MouseAction::Move, None) => 3,
+          (_, button) => button.map_or(0, usize::from),
+        };
+        let button = usize::from(modifiers) | button | usize::from(action);
         let Point { x: column, y: line } = coords;
-        let release = if let MouseAction::Release = action { "m" } else { "M" };
+        let release = matches!(action, MouseAction::Release);
 
-        TmuxKey::Esc(format!("<{modifiers};{column};{line}{release}"))
+        TmuxKey::Bytes(mouse.report(button, column, line, release)?)
       }
 
-      Event::Named(s) => {
-        // tmux's key names differs from kakoune's here, so this is a special case
-        if s == "tab" && modifiers.shift {
-          return TmuxKey::Key("BTab".into());
-        }
-
-        let mut tmux_key = String::new();
-
-        if modifiers.alt {
-          tmux_key.push_str("M-");
-        }
-        if modifiers.ctrl {
-          tmux_key.push_str("C-");
-        }
-        if modifiers.shift {
-          tmux_key.push_str("S-");
-        }
+      Event::Named(name) => TmuxKey::Key(tmux_key_name(name, &modifiers)?),
+    };
 
-        tmux_key.push_str(match s {
-          "lt" => "<",
-          "gt" => ">",
-          "plus" => "+",
-          "minus" => "-",
-          "percent" => "%",
-          "semicolon" => "\\;",
-          "quote" => "'",
-          "dquote" => "\"",
-          "up" => "Up",
-          "down" => "Down",
-          "left" => "Left",
-          "right" => "Right",
-          "esc" => "Escape",
-          "ret" => "Enter",
-          "tab" => "Tab",
-          "space" => "Space",
-          "backspace" => "BSpace",
-          "del" => "DC",
-          key => key,
-        });
-
-        TmuxKey::Key(tmux_key)
-      }
-    }
+    Some(key)
   }
 }
 
@@ -163,50 +542,42 @@ impl<'a> TryFrom<&'a str> for Key<'a> {
   type Error = anyhow::Error;
 
   fn try_from(key: &'a str) -> Result<Self> {
-    let mut key = if key.starts_with('<') {
-      &key[1..key.len() - 1]
-    } else {
+    let Some(key) = key.strip_prefix('<').and_then(|key| key.strip_suffix('>')) else {
       return Ok(Self {
         event: Event::Named(key),
         modifiers: Modifiers::new(),
       });
     };
 
-    let mut modifiers = Modifiers::new();
-
-    while key.len() >= 3 {
-      match &key[..2] {
-        "a-" => modifiers.alt = true,
-        "c-" => modifiers.ctrl = true,
-        "s-" => modifiers.shift = true,
+    let (modifiers, key) = Modifiers::strip(key, Modifiers::KAKOUNE);
 
-        _ => break,
-      }
-
-      key = &key[2..];
-    }
+    let parts: Vec<_> = key.split(':').collect();
+    let part = |index: usize| {
+      parts
+        .get(index)
+        .copied()
+        .ok_or_else(|| anyhow::anyhow!("malformed key {key:?}"))
+    };
 
     let event = if key.starts_with("mouse") {
-      let parts: Vec<_> = key.split(':').collect();
-      if parts[1] == "move" {
+      if part(1)? == "move" {
         Event::Mouse {
           action: MouseAction::Move,
           button: None,
-          coords: parts[2].parse()?,
+          coords: part(2)?.parse()?,
         }
       } else {
         Event::Mouse {
-          action: parts[1].parse()?,
-          button: Some(parts[2].parse()?),
-          coords: parts[3].parse()?,
+          action: part(1)?.parse()?,
+          button: Some(part(2)?.parse()?),
+          coords: part(3)?.parse()?,
         }
       }
-    } else if key.starts_with("scroll") {
-      let parts: Vec<_> = key.split(':').collect();
-
+    } else if key.starts_with("scroll") || key.starts_with("hscroll") {
       Event::Scroll {
-        amount: parts[1].parse()?,
+        amount: part(1)?.parse()?,
         coords: parts.get(2).and_then(|c| c.parse().ok()),
+        horizontal: parts[0] == "hscroll",
       }
     } else {
       Event::Named(key)
@@ -222,6 +593,7 @@ enum Event<'a> {
   Scroll {
     amount: i32,
     coords: Option<Point>,
+    horizontal: bool,
   },
 
   Mouse {
@@ -231,16 +603,23 @@ enum Event<'a> {
   },
 }
 
+#[derive(Clone, Copy)]
 enum MouseButton {
   Left,
+  Middle,
   Right,
+  /// Buttons 4 to 11, as X numbers them: the wheel, then extra buttons such as back and forward.
+  Extra(u8),
 }
 
 impl From<MouseButton> for usize {
   fn from(button: MouseButton) -> usize {
     match button {
       MouseButton::Left => 0,
+      MouseButton::Middle => 1,
       MouseButton::Right => 2,
+      MouseButton::Extra(button @ 4..=7) => 64 + usize::from(button - 4),
+      MouseButton::Extra(button) => 128 + usize::from(button - 8),
     }
   }
 }
@@ -251,9 +630,14 @@ impl FromStr for MouseButton {
   fn from_str(s: &str) -> Result<Self> {
     match s {
       "left" => Ok(Self::Left),
+      "middle" => Ok(Self::Middle),
       "right" => Ok(Self::Right),
 
-      _ => anyhow::bail!("unknown mouse button {s:?}"),
+      s => match s.strip_prefix("button").map(str::parse) {
+        Some(Ok(button @ 4..=11)) => Ok(Self::Extra(button)),
+
+        _ => anyhow::bail!("unknown mouse button {s:?}"),
+      },
     }
   }
 }
@@ -288,19 +672,6 @@ impl FromStr for MouseAction {
   }
 }
 
-#[derive(Default)]
-struct Modifiers {
-  alt: bool,
-  ctrl: bool,
-  shift: bool,
-}
-
-impl Modifiers {
-  pub fn new() -> Self {
-    Self::default()
-  }
-}
-
 impl From<Modifiers> for usize {
   fn from(modifiers: Modifiers) -> usize {
     let alt = if modifiers.alt { 8 } else { 0 };
@@ -310,3 +681,166 @@ impl From<Modifiers> for usize {
     alt | ctrl | shift
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{Action, Key, Keymap, MouseButton, TmuxKey, KEY_NAMES};
+  use crate::backend::{Mouse, MouseEncoding, MouseTracking};
+
+  /// What tmux is sent for the kakoune key `key`, if it's sent by name.
+  fn tmux(key: &str) -> Option<String> {
+    match Key::try_from(key).ok()?.translate(Mouse::default())? {
+      TmuxKey::Key(name) => Some(name),
+      TmuxKey::Bytes(_) => None,
+    }
+  }
+
+  #[test]
+  fn unbinding() {
+    let mut keymap = Keymap::default();
+    keymap.bind("quit=").unwrap();
+
+    assert_eq!(keymap.action("<c-space>"), None);
+    assert_eq!(keymap.action("<a-space>"), Some(Action::Detach));
+  }
+
+  #[test]
+  fn rebinding() {
+    let mut keymap = Keymap::default();
+    keymap.bind("quit=<a-space>").unwrap();
+
+    // the key is taken from detach, and quit's old key is freed
+    assert_eq!(keymap.action("<a-space>"), Some(Action::Quit));
+    assert_eq!(keymap.action("<c-space>"), None);
+    assert!(!keymap.bindings.iter().any(|(action, _)| *action == Action::Detach));
+  }
+
+  #[test]
+  fn invalid_bindings() {
+    for pairs in ["exit=<c-q>", "quit", "quit<c-q>"] {
+      assert!(Keymap::default().bind(pairs).is_err(), "{pairs:?} was accepted");
+    }
+  }
+
+  #[test]
+  fn named_keys() {
+    for (kak_name, tmux_name) in KEY_NAMES {
+      assert_eq!(
+        tmux(&format!("<{kak_name}>")).as_deref(),
+        Some(*tmux_name),
+        "<{kak_name}>"
+      );
+      assert_eq!(
+        tmux(&format!("<a-{kak_name}>")),
+        Some(format!("M-{tmux_name}")),
+        "<a-{kak_name}>"
+      );
+    }
+  }
+
+  #[test]
+  fn function_keys() {
+    for number in 1..=12 {
+      assert_eq!(tmux(&format!("<F{number}>")), Some(format!("F{number}")));
+      assert_eq!(tmux(&format!("<f{number}>")), Some(format!("F{number}")));
+      assert_eq!(tmux(&format!("<c-F{number}>")), Some(format!("C-F{number}")));
+    }
+  }
+
+  #[test]
+  fn modifiers() {
+    assert_eq!(tmux("<s-tab>").as_deref(), Some("BTab"));
+    assert_eq!(tmux("<s-up>").as_deref(), Some("S-Up"));
+    assert_eq!(tmux("<c-a-x>").as_deref(), Some("M-C-x"));
+    assert_eq!(tmux("<s-x>").as_deref(), Some("X"));
+    assert_eq!(tmux("<s-é>").as_deref(), Some("É"));
+    assert_eq!(tmux("x").as_deref(), Some("x"));
+  }
+
+  #[test]
+  fn malformed_keys() {
+    assert_eq!(tmux("<"), Some("<".to_string()));
+    assert!(Key::try_from("<mouse>").is_err());
+    assert!(Key::try_from("<mouse:press>").is_err());
+    assert!(Key::try_from("<scroll>").is_err());
+  }
+
+  /// What the program is sent for the kakoune mouse keys `keys`, in turn, when it has asked for every mouse
+  /// event in SGR encoding.
+  fn reports(keys: &[&str]) -> Vec<String> {
+    let mouse = Mouse {
+      tracking: MouseTracking::Any,
+      encoding: MouseEncoding::Sgr,
+    };
+    let mut held = None;
+
+    keys
+      .iter()
+      .map(|key| {
+        let mut key = Key::try_from(*key).unwrap();
+        key.track_buttons(&mut held);
+
+        match key.translate(mouse) {
+          Some(TmuxKey::Bytes(bytes)) => String::from_utf8(bytes).unwrap(),
+          Some(TmuxKey::Key(name)) => name,
+          None => String::new(),
+        }
+      })
+      .collect()
+  }
+
+  #[test]
+  fn mouse_buttons() {
+    assert_eq!(
+      reports(&[
+        "<mouse:press:left:1.2>",
+        "<mouse:press:middle:1.2>",
+        "<mouse:release:right:1.2>"
+      ]),
+      ["\x1b[<0;2;1M", "\x1b[<1;2;1M", "\x1b[<2;2;1m"]
+    );
+
+    for (button, number) in (4..=7).zip(64..).chain((8..=11).zip(128..)) {
+      let button: MouseButton = format!("button{button}").parse().unwrap();
+      assert_eq!(usize::from(button), number);
+    }
+
+    assert!("button3".parse::<MouseButton>().is_err());
+    assert!("button12".parse::<MouseButton>().is_err());
+    assert!(Key::try_from("<mouse:press:button12:1.1>").is_err());
+  }
+
+  #[test]
+  fn drags() {
+    assert_eq!(
+      reports(&[
+        "<mouse:move:1.1>",
+        "<mouse:press:left:1.1>",
+        "<mouse:move:2.1>",
+        "<mouse:release:left:2.1>",
+        "<mouse:move:3.1>",
+      ]),
+      [
+        "\x1b[<35;1;1M",
+        "\x1b[<0;1;1M",
+        "\x1b[<32;1;2M",
+        "\x1b[<0;1;2m",
+        "\x1b[<35;1;3M"
+      ]
+    );
+  }
+
+  #[test]
+  fn scroll_fallback() {
+    // without mouse reporting, wheel events are sent as arrow keys
+    let fallback = |key| match Key::try_from(key).unwrap().translate(Mouse::default()) {
+      Some(TmuxKey::Key(name)) => name,
+      _ => panic!("{key} wasn't sent as a key"),
+    };
+
+    assert_eq!(fallback("<scroll:-1:1.1>"), "Up");
+    assert_eq!(fallback("<scroll:1:1.1>"), "Down");
+    assert_eq!(fallback("<hscroll:-1:1.1>"), "Left");
+    assert_eq!(fallback("<hscroll:1:1.1>"), "Right");
+  }
+}
@@ -3,16 +3,24 @@ use std::thread::{self, JoinHandle};
 use anyhow::Result;
 
 mod keys;
+mod paste;
 mod quit;
 mod refresh;
 mod resize;
 
-pub use self::{keys::Keys, quit::Quit, refresh::Refresh, resize::Resize};
+pub use self::{
+  keys::{Keymap, Keys},
+  paste::Paste,
+  quit::{Exit, Quit},
+  refresh::{Rate, Refresh},
+  resize::Resize,
+};
 use crate::kakoune::Kakoune;
 
 pub enum Step {
   Next,
   Quit,
+  Detach,
 }
 
 pub trait Spawn {
@@ -34,6 +42,11 @@ pub trait Spawn {
             quit.quit();
           }
 
+          Ok(Step::Detach) => {
+            let _ignore = kakoune.debug(format!("{}::step: detaching", Self::NAME));
+            quit.detach();
+          }
+
           Err(err) => {
             let _ignore = kakoune.debug(format!("{}::step: {err:?}", Self::NAME));
             quit.quit();
@@ -0,0 +1,85 @@
+use std::sync::{mpsc::Sender, Arc};
+
+use anyhow::Result;
+
+use super::{Spawn, Step};
+use crate::{
+  backend::{Backend, Event},
+  fifo::Fifo,
+};
+
+/// Pastes text written to the paste fifo by `popup-paste`, a whole register at a time, with its selections
+/// on lines of their own.
+pub struct Paste {
+  backend: Arc<dyn Backend>,
+  paste_fifo: Fifo,
+  refresh: Sender<Event>,
+}
+
+impl Paste {
+  pub fn new(backend: Arc<dyn Backend>, paste_fifo: Fifo, refresh: Sender<Event>) -> Self {
+    Self {
+      backend,
+      paste_fifo,
+      refresh,
+    }
+  }
+}
+
+impl Spawn for Paste {
+  const NAME: &'static str = "paste";
+
+  fn step(&self) -> Result<Step> {
+    let text = unquote(&self.paste_fifo.read()?).join("\n");
+
+    if !text.is_empty() {
+      self.backend.paste(&text)?;
+      self.refresh.send(Event::Input)?;
+    }
+
+    Ok(Step::Next)
+  }
+}
+
+/// Splits the output of `echo -quoting kakoune` back into its arguments, each of which is wrapped in single
+/// quotes with any quotes inside doubled.
+fn unquote(quoted: &str) -> Vec<String> {
+  let mut args = Vec::new();
+  let mut chars = quoted.trim_end_matches('\n').chars().peekable();
+
+  while let Some(c) = chars.next() {
+    if c != '\'' {
+      continue;
+    }
+
+    let mut arg = String::new();
+
+    while let Some(c) = chars.next() {
+      match c {
+        '\'' if chars.peek() == Some(&'\'') => {
+          chars.next();
+          arg.push('\'');
+        }
+        '\'' => break,
+        c => arg.push(c),
+      }
+    }
+
+    args.push(arg);
+  }
+
+  args
+}
+
+#[cfg(test)]
+mod tests {
+  use super::unquote;
+
+  #[test]
+  fn unquote_selections() {
+    assert_eq!(unquote("'one' 'two'"), ["one", "two"]);
+    assert_eq!(unquote("'it''s' '{}' ''\n"), ["it's", "{}", ""]);
+    assert_eq!(unquote("'multi\nline'"), ["multi\nline"]);
+    assert_eq!(unquote(""), Vec::<String>::new());
+  }
+}
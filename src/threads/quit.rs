@@ -2,30 +2,56 @@ use std::sync::Arc;
 
 use parking_lot::{Condvar, Mutex};
 
+/// How a popup was closed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+  /// The popup and its command are gone.
+  Quit,
+  /// The popup is hidden but its command keeps running.
+  Detach,
+}
+
 #[derive(Clone)]
 pub struct Quit {
-  inner: Arc<(Mutex<bool>, Condvar)>,
+  inner: Arc<(Mutex<Option<Exit>>, Condvar)>,
 }
 
 impl Quit {
   pub fn new() -> Self {
     Self {
-      inner: Arc::new((Mutex::new(false), Condvar::new())),
+      inner: Arc::new((Mutex::new(None), Condvar::new())),
     }
   }
 
   pub fn quit(&self) {
+    self.exit(Exit::Quit);
+  }
+
+  pub fn detach(&self) {
+    self.exit(Exit::Detach);
+  }
+
+  /// Records the first exit reason, later ones are ignored.
+  fn exit(&self, exit: Exit) {
     let (mutex, condvar) = &*self.inner;
-    *mutex.lock() = true;
+    mutex.lock().get_or_insert(exit);
     condvar.notify_one();
   }
 
-  pub fn wait(&self) {
+  pub fn wait(&self) -> Exit {
     let (mutex, condvar) = &*self.inner;
-    condvar.wait(&mut mutex.lock());
+    let mut exit = mutex.lock();
+
+    loop {
+      if let Some(exit) = *exit {
+        return exit;
+      }
+
+      condvar.wait(&mut exit);
+    }
   }
 
   pub fn is_quit(&self) -> bool {
-    *self.inner.0.lock()
+    self.inner.0.lock().is_some()
   }
 }
@@ -1,43 +1,88 @@
 use std::{
-  sync::mpsc::{self, Receiver, Sender},
-  thread::{self, JoinHandle},
-  time::Duration,
+  sync::{
+    mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    Arc,
+  },
+  time::{Duration, Instant},
 };
 
 use anyhow::Result;
+use parking_lot::Mutex;
 
 use super::{Spawn, Step};
-use crate::{buffer::Buffer, escape, kakoune::Kakoune, tmux::Tmux};
+use crate::{
+  backend::{Backend, DisplayInfo, Event},
+  buffer::{Buffer, Grid, Palette},
+  escape,
+  kakoune::Kakoune,
+  popup::Settings,
+  view::{Selection, View},
+};
+
+/// Bounds on the time between redraws. The interval drops to `min` after input, and backs off towards
+/// `max` while the command only produces output.
+#[derive(Clone, Copy)]
+pub struct Rate {
+  pub min: Duration,
+  pub max: Duration,
+}
 
 pub struct Refresh {
-  pub sender: Sender<()>,
-  receiver: Receiver<()>,
+  pub sender: Sender<Event>,
+  receiver: Receiver<Event>,
 
   kakoune: Kakoune,
-  tmux: Tmux,
-  title: String,
+  backend: Arc<dyn Backend>,
 
-  _events: JoinHandle<Result<()>>,
+  rate: Rate,
+  palette: Palette,
+  state: Mutex<State>,
+  view: View,
 }
 
-impl Refresh {
-  const RATE: Duration = Duration::from_millis(100);
+struct State {
+  /// The last frame sent to kakoune.
+  frame: Option<Frame>,
+  drawn: Instant,
+  interval: Duration,
+}
+
+/// Everything that ends up in the `info` box, compared to skip redrawing an unchanged screen. The capture is
+/// compared once decoded, as the same screen can be captured with different escape sequences.
+#[derive(PartialEq, Eq)]
+struct Frame {
+  info: DisplayInfo,
+  grid: Grid,
+  title: String,
+  selection: Option<Selection>,
+}
 
-  pub fn new(kakoune: Kakoune, tmux: Tmux, title: Option<String>) -> Self {
+impl Refresh {
+  /// Creates a refresh that redraws whenever the terminal reports a change.
+  pub fn new(kakoune: Kakoune, settings: &Settings, backend: Arc<dyn Backend>, view: View) -> Self {
+    let rate = settings.refresh_rate;
     let (sender, receiver) = mpsc::channel();
 
+    backend.subscribe(sender.clone());
+
+    // draw the initial screen before any changes are reported
+    let _ignore = sender.send(Event::Input);
+
     Self {
-      sender: sender.clone(),
+      sender,
       receiver,
 
       kakoune,
-      tmux,
-      title: title.unwrap_or_default(),
+      backend,
 
-      _events: thread::spawn(move || loop {
-        sender.send(())?;
-        thread::sleep(Self::RATE);
+      rate,
+      palette: settings.palette.clone(),
+      state: Mutex::new(State {
+        frame: None,
+        drawn: Instant::now(),
+        interval: rate.min,
       }),
+      view,
     }
   }
 }
@@ -46,16 +91,70 @@ impl Spawn for Refresh {
   const NAME: &'static str = "refresh";
 
   fn step(&self) -> Result<Step> {
-    self.receiver.recv()?;
+    let mut input = false;
+    let mut event = self.receiver.recv()?;
+    let mut state = self.state.lock();
+
+    // a burst of changes only needs a single redraw, so they're collected until the next one is due
+    loop {
+      if event == Event::Input && !input {
+        input = true;
+        state.interval = self.rate.min;
+      }
 
-    let buffer = Buffer::new(self.tmux.display_info()?, self.tmux.capture_pane()?);
-    let markup = escape::kak(buffer.markup()?);
-    let title = escape::kak(&self.title);
+      let due = (state.drawn + state.interval).saturating_duration_since(Instant::now());
+
+      event = match self.receiver.recv_timeout(due) {
+        Ok(event) => event,
+        Err(RecvTimeoutError::Timeout) => break,
+        Err(err) => return Err(err.into()),
+      };
+    }
+
+    if !input {
+      state.interval = (state.interval * 2).min(self.rate.max);
+    }
+
+    let mut info = self.backend.display_info()?;
+    let mut title = self.view.title();
+    let scroll = self.view.scroll().map(|scroll| scroll.min(info.history_size));
+
+    if let Some(scroll) = scroll {
+      // the cursor moves down with the rest of the screen, usually out of view
+      info.cursor.y += scroll;
+
+      let position = format!("[{scroll}/{}]", info.history_size);
+      title = if title.is_empty() {
+        position
+      } else {
+        format!("{title} {position}")
+      };
+    }
+
+    let capture = self.backend.capture_pane(scroll.unwrap_or_default())?;
+
+    let frame = Frame {
+      grid: Grid::new(&capture, Some(info.size.width)),
+      info,
+      title,
+      selection: self.view.selection(),
+    };
+
+    if state.frame.as_ref() == Some(&frame) {
+      return Ok(Step::Next);
+    }
+
+    let buffer = Buffer::new(frame.info.clone(), frame.grid.clone(), frame.selection);
+    let markup = escape::kak(buffer.markup(&self.palette));
+    let title = escape::kak(&frame.title);
 
     self
       .kakoune
       .eval(format!("info -style modal -title {title} -markup {markup}"))?;
 
+    state.frame = Some(frame);
+    state.drawn = Instant::now();
+
     Ok(Step::Next)
   }
 }
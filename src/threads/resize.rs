@@ -1,24 +1,29 @@
-use std::sync::mpsc::Sender;
+use std::sync::{mpsc::Sender, Arc};
 
 use anyhow::Result;
 
 use super::{Spawn, Step};
-use crate::{fifo::Fifo, geometry::Size, tmux::Tmux};
+use crate::{
+  backend::{Backend, Event},
+  fifo::Fifo,
+  geometry::Size,
+  view::View,
+};
 
 pub struct Resize {
-  padding: usize,
-  tmux: Tmux,
+  backend: Arc<dyn Backend>,
   resize_fifo: Fifo,
-  refresh: Sender<()>,
+  refresh: Sender<Event>,
+  view: View,
 }
 
 impl Resize {
-  pub fn new(padding: usize, tmux: Tmux, resize_fifo: Fifo, refresh: Sender<()>) -> Self {
+  pub fn new(backend: Arc<dyn Backend>, resize_fifo: Fifo, refresh: Sender<Event>, view: View) -> Self {
     Self {
-      padding,
-      tmux,
+      backend,
       resize_fifo,
       refresh,
+      view,
     }
   }
 }
@@ -27,11 +32,11 @@ impl Spawn for Resize {
   const NAME: &'static str = "resize";
 
   fn step(&self) -> Result<Step> {
-    let new_size: Size = serde_json::from_str(&self.resize_fifo.read()?)?;
-    let new_size = new_size.padded(self.padding)?;
+    let window: Size = serde_json::from_str(&self.resize_fifo.read()?)?;
+    self.view.set_window(window);
 
-    self.tmux.set_size(new_size)?;
-    self.refresh.send(())?;
+    self.backend.set_size(self.view.size()?)?;
+    self.refresh.send(Event::Input)?;
 
     Ok(Step::Next)
   }
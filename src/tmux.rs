@@ -1,52 +1,260 @@
-use std::{ffi::OsStr, process::Command, sync::Arc, time::SystemTime};
+use std::{
+  env,
+  ffi::OsStr,
+  fs::{self, DirBuilder},
+  io::{self, BufRead, BufReader, Write},
+  os::unix::fs::{DirBuilderExt, FileTypeExt},
+  path::{Path, PathBuf},
+  process::{Child, ChildStdin, Command, Stdio},
+  sync::{
+    mpsc::{self, Receiver, Sender},
+    Arc,
+  },
+  thread,
+  time::SystemTime,
+};
 
 use anyhow::{Context, Result};
+use nix::unistd;
 use parking_lot::Mutex;
-use serde::Deserialize;
 
-use crate::geometry::{Point, Size};
+use crate::{
+  backend::{Backend, DisplayInfo, Event, Key},
+  geometry::Size,
+};
 
-#[derive(Deserialize)]
-pub struct DisplayInfo {
-  pub size: Size,
-  pub cursor: Point,
+/// A tmux server on its own socket, by default one per kakoune session so that popups from different
+/// sessions, or users, never share a server.
+#[derive(Clone)]
+pub struct Server {
+  socket: PathBuf,
 }
 
-pub enum Key {
-  Key(String),
-  Esc(String),
+impl Server {
+  pub fn new(socket: PathBuf) -> Self {
+    Self { socket }
+  }
+
+  /// The server belonging to `kak_session`.
+  pub fn for_kak_session(kak_session: &str) -> Result<Self> {
+    Ok(Self::new(Self::dir()?.join(kak_session)))
+  }
+
+  /// The directory holding the per-session sockets, laid out like tmux's own `tmux-UID` directory.
+  fn dir() -> Result<PathBuf> {
+    let dir = env::var_os("TMUX_TMPDIR")
+      .map_or_else(env::temp_dir, PathBuf::from)
+      .join(format!("kak-popup-{}", unistd::getuid()));
+
+    DirBuilder::new()
+      .recursive(true)
+      .mode(0o700)
+      .create(&dir)
+      .with_context(|| format!("failed to create {}", dir.display()))?;
+
+    Ok(dir)
+  }
+
+  /// Every server with a socket in the per-session directory, including stale sockets left by crashed
+  /// servers.
+  pub fn all() -> Result<Vec<Self>> {
+    let mut servers = Vec::new();
+
+    for entry in fs::read_dir(Self::dir()?)? {
+      let entry = entry?;
+
+      if entry.file_type()?.is_socket() {
+        servers.push(Self::new(entry.path()));
+      }
+    }
+
+    servers.sort_by(|a, b| a.socket.cmp(&b.socket));
+
+    Ok(servers)
+  }
+
+  pub fn socket(&self) -> &Path {
+    &self.socket
+  }
+
+  /// The kakoune session the socket was derived from.
+  pub fn kak_session(&self) -> Option<&str> {
+    self.socket.file_name()?.to_str()
+  }
+
+  /// Whether a server is listening on the socket.
+  pub fn is_running(&self) -> bool {
+    self.command("list-sessions", ["-F", ""]).is_ok()
+  }
+
+  pub fn has_session(&self, session: &str) -> bool {
+    self.command("has-session", ["-t", &format!("={session}:")]).is_ok()
+  }
+
+  /// Checks that `name` can be used for a new session.
+  pub fn validate_name(&self, name: &str) -> Result<()> {
+    // tmux silently replaces these in session names
+    anyhow::ensure!(
+      !name.is_empty() && !name.contains([':', '.']),
+      "invalid popup name {name:?}, names must be non-empty and not contain ':' or '.'"
+    );
+    anyhow::ensure!(!self.has_session(name), "a popup named {name:?} already exists");
+
+    Ok(())
+  }
+
+  /// All sessions on the server, or none if the server isn't running.
+  pub fn sessions(&self) -> Result<Vec<Tmux>> {
+    let Ok(sessions) = self.command("list-sessions", ["-F", "#{session_name}"]) else {
+      return Ok(Vec::new());
+    };
+
+    String::from_utf8(sessions)?
+      .lines()
+      .map(|session| Tmux::inspect(self.clone(), session))
+      .collect()
+  }
+
+  /// Stops the server and all of its sessions.
+  pub fn kill(&self) -> Result<()> {
+    // fails if the server is already exiting, which is just as good
+    let _ignore = self.command::<_, &str>("kill-server", []);
+
+    self.remove()
+  }
+
+  /// Removes the socket, which tmux leaves behind when the server exits.
+  pub fn remove(&self) -> Result<()> {
+    match fs::remove_file(&self.socket) {
+      Err(err) if err.kind() != io::ErrorKind::NotFound => {
+        Err(err).with_context(|| format!("failed to remove {}", self.socket.display()))
+      }
+      _ => Ok(()),
+    }
+  }
+
+  fn command<I, S>(&self, command: &str, args: I) -> Result<Vec<u8>>
+  where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+  {
+    let output = Command::new("tmux")
+      .arg("-S")
+      .arg(&self.socket)
+      .arg(command)
+      .args(args)
+      .output()
+      .with_context(|| format!("tmux {command}"))?;
+
+    if !output.status.success() {
+      return Err(anyhow::anyhow!(
+        "tmux {command} exited with non-zero status: {}, err: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr),
+      ));
+    }
+
+    Ok(output.stdout)
+  }
 }
 
 #[derive(Clone)]
 pub struct Tmux {
   pub session: String,
 
+  server: Server,
   size: Arc<Mutex<Size>>,
+  /// The control mode client used for frequent commands, absent for sessions that are only inspected.
+  control: Option<Arc<Control>>,
 }
 
 impl Tmux {
-  pub fn new(command: &[String], size: Size) -> Result<Self> {
-    let session = SystemTime::now()
-      .duration_since(SystemTime::UNIX_EPOCH)?
-      .as_nanos()
-      .to_string();
+  pub fn new(server: Server, name: Option<String>, command: &[String], size: Size) -> Result<Self> {
+    let session = match name {
+      Some(name) => {
+        server.validate_name(&name)?;
+        name
+      }
 
-    let tmux = Self {
+      None => SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_nanos()
+        .to_string(),
+    };
+
+    let mut tmux = Self {
       session,
+      server,
       size: Arc::new(Mutex::new(size)),
+      control: None,
     };
 
     tmux.start(command, size)?;
     tmux.set_option("status", "off")?;
+    tmux.control = Some(Arc::new(Control::new(&tmux.server, &tmux.target())?));
+
+    Ok(tmux)
+  }
+
+  /// Connects to an existing session, such as one left running by a detached popup.
+  pub fn attach(server: Server, session: &str, size: Size) -> Result<Self> {
+    anyhow::ensure!(server.has_session(session), "no popup named {session:?}");
+
+    let tmux = Self {
+      session: session.to_string(),
+      control: Some(Arc::new(Control::new(&server, &format!("={session}:"))?)),
+      server,
+      size: Arc::new(Mutex::new(size)),
+    };
+
+    tmux.resize_window(size)?;
+
+    Ok(tmux)
+  }
+
+  /// Connects to an existing session only to query it, without a control mode client or resizing it.
+  pub fn inspect(server: Server, session: &str) -> Result<Self> {
+    anyhow::ensure!(server.has_session(session), "no popup named {session:?}");
+
+    let tmux = Self {
+      session: session.to_string(),
+      server,
+      size: Arc::new(Mutex::new(Size { height: 0, width: 0 })),
+      control: None,
+    };
+
+    *tmux.size.lock() = tmux.window_size()?;
 
     Ok(tmux)
   }
 
+  /// The exact target for this session, as tmux otherwise matches session names by prefix.
+  fn target(&self) -> String {
+    format!("={}:", self.session)
+  }
+
+  /// Runs a command through the control mode client, avoiding a new tmux process, or through a new one
+  /// for sessions that are only inspected.
+  fn control_command(&self, command: &str, args: &[&str]) -> Result<Vec<u8>> {
+    match &self.control {
+      Some(control) => control
+        .command(command, args)
+        .with_context(|| format!("tmux {command}")),
+      None => self.server.command(command, args),
+    }
+  }
+
   fn start(&self, command: &[String], size: Size) -> Result<()> {
     let width = size.width.to_string();
     let height = size.height.to_string();
 
+    // the server exits along with its last session, even if the user's config says otherwise
     let mut args = vec![
+      ";",
+      "set-option",
+      "-s",
+      "exit-empty",
+      "on",
       ";",
       "new-session",
       "-s",
@@ -61,38 +269,138 @@ impl Tmux {
 
     args.extend(command.iter().map(String::as_str));
 
-    tmux_command("start", &args)?;
+    self.server.command("start", &args)?;
 
     Ok(())
   }
 
-  pub fn kill(&self) -> Result<()> {
-    tmux_command("kill-session", ["-t", &self.session])?;
+  fn set_option(&self, option: &str, value: &str) -> Result<()> {
+    self
+      .server
+      .command("set-option", ["-t", &self.target(), option, value])?;
 
     Ok(())
   }
 
-  fn set_option(&self, option: &str, value: &str) -> Result<()> {
-    tmux_command("set-option", ["-t", &self.session, option, value])?;
+  /// Stores `value` in the session, where it outlives this process.
+  pub fn set_user_option(&self, option: &str, value: &str) -> Result<()> {
+    self.set_option(&format!("@{option}"), value)
+  }
+
+  pub fn user_option(&self, option: &str) -> Result<String> {
+    let value = self
+      .server
+      .command("show-options", ["-qv", "-t", &self.target(), &format!("@{option}")])?;
+    let value = String::from_utf8(value)?;
+
+    Ok(value.strip_suffix('\n').unwrap_or(&value).to_string())
+  }
+
+  pub fn server(&self) -> &Server {
+    &self.server
+  }
+
+  pub fn size(&self) -> Size {
+    *self.size.lock()
+  }
+
+  fn window_size(&self) -> Result<Size> {
+    const FORMAT_STR: &str = r#"{ "width": #{window_width}, "height": #{window_height} }"#;
+
+    let content = self
+      .server
+      .command("display", ["-t", &self.target(), "-p", FORMAT_STR])?;
+
+    serde_json::from_slice(&content).with_context(|| format!("Failed to parse: {}", String::from_utf8_lossy(&content)))
+  }
+
+  fn resize_window(&self, size: Size) -> Result<()> {
+    self.control_command(
+      "resize-window",
+      &[
+        "-t",
+        &self.target(),
+        "-x",
+        &size.width.to_string(),
+        "-y",
+        &size.height.to_string(),
+      ],
+    )?;
 
     Ok(())
   }
+}
 
-  pub fn send_keys(&self, keys: Key) -> Result<()> {
-    match keys {
-      Key::Key(s) => tmux_command("send-keys", ["-t", &self.session, &s])?,
-      Key::Esc(s) => tmux_command("send-keys", ["-t", &self.session, "-l", &format!("\x1b[{s}")])?,
-    };
+impl Backend for Tmux {
+  fn send_keys(&self, keys: Vec<Key>) -> Result<()> {
+    let target = self.target();
+
+    // send-keys can't mix named keys with hex, so each run of either is sent in one go
+    for run in keys.chunk_by(|a, b| matches!((a, b), (Key::Key(_), Key::Key(_)) | (Key::Bytes(_), Key::Bytes(_)))) {
+      let mut args = vec!["-t".to_string(), target.clone()];
+
+      // sent as hex, since reports such as X10 mouse coordinates aren't necessarily valid UTF-8
+      if let Key::Bytes(_) = run[0] {
+        args.push("-H".to_string());
+      }
+
+      for key in run {
+        match key {
+          Key::Key(key) => args.push(key.clone()),
+          Key::Bytes(bytes) => args.extend(bytes.iter().map(|byte| format!("{byte:02x}"))),
+        }
+      }
+
+      let args: Vec<_> = args.iter().map(String::as_str).collect();
+      self.control_command("send-keys", &args)?;
+    }
+
+    Ok(())
+  }
+
+  fn paste(&self, text: &str) -> Result<()> {
+    // a buffer per session, deleted once pasted, so that popups can't paste each other's text
+    let buffer = format!("kak-popup-{}", self.session);
+
+    self.control_command("set-buffer", &["-b", &buffer, "--", text])?;
+    self.control_command("paste-buffer", &["-t", &self.target(), "-b", &buffer, "-d", "-p"])?;
 
     Ok(())
   }
 
-  pub fn capture_pane(&self) -> Result<Vec<u8>> {
-    tmux_command("capture-pane", ["-t", &self.session, "-p", "-e"])
+  fn capture_pane(&self, scroll: usize) -> Result<Vec<u8>> {
+    if scroll == 0 {
+      return self.control_command("capture-pane", &["-t", &self.target(), "-p", "-e"]);
+    }
+
+    // negative lines are in the history, which ends at -1
+    let start = -(scroll as isize);
+    let end = start + self.size().height as isize - 1;
+
+    self.control_command(
+      "capture-pane",
+      &[
+        "-t",
+        &self.target(),
+        "-p",
+        "-e",
+        "-S",
+        &start.to_string(),
+        "-E",
+        &end.to_string(),
+      ],
+    )
+  }
+
+  fn capture_history(&self) -> Result<Vec<u8>> {
+    self.control_command(
+      "capture-pane",
+      &["-t", &self.target(), "-p", "-e", "-S", "-", "-E", "-"],
+    )
   }
 
-  pub fn display_info(&self) -> Result<DisplayInfo> {
-    const FORMAT_STR: &str = r#"{
+  fn display_info(&self) -> Result<DisplayInfo> {
+    const FORMAT_STR: &str = r##"{
       "size": {
         "width": #{pane_width},
         "height": #{pane_height}
@@ -100,12 +408,17 @@ impl Tmux {
       "cursor": {
         "x": #{cursor_x},
         "y": #{cursor_y}
+      },
+      "history_size": #{history_size},
+      "mouse": {
+        "tracking": "#{?mouse_all_flag,any,#{?mouse_button_flag,button,#{?mouse_standard_flag,normal,none}}}",
+        "encoding": "#{?mouse_sgr_flag,sgr,#{?mouse_utf8_flag,utf8,default}}"
       }
-    }"#;
+    }"##;
 
     // OpenSUSE's tmux replaces newlines with _ so we remove the newlines
     let format_str = FORMAT_STR.replace('\n', " ");
-    let content = tmux_command("display", ["-t", &self.session, "-p", &format_str])?;
+    let content = self.control_command("display", &["-t", &self.target(), "-p", &format_str])?;
 
     let display_info: DisplayInfo = serde_json::from_slice(&content)
       .with_context(|| format!("Failed to parse: {}", String::from_utf8_lossy(&content)))?;
@@ -119,48 +432,181 @@ impl Tmux {
     Ok(display_info)
   }
 
-  pub fn set_size(&self, size: Size) -> Result<()> {
+  fn set_size(&self, size: Size) -> Result<()> {
     *self.size.lock() = size;
 
     self.resize_window(size)
   }
 
-  fn resize_window(&self, size: Size) -> Result<()> {
-    tmux_command(
-      "resize-window",
-      [
-        "-t",
-        &self.session,
-        "-x",
-        &size.width.to_string(),
-        "-y",
-        &size.height.to_string(),
-      ],
-    )?;
+  fn kill(&self) -> Result<()> {
+    // fails if the command has already exited and taken the session with it
+    let killed = self.server.command("kill-session", ["-t", &self.target()]);
 
-    Ok(())
+    // the server exits along with its last session, but leaves its socket behind
+    if self.server.sessions()?.is_empty() {
+      self.server.kill()?;
+    }
+
+    killed.map(drop)
+  }
+
+  fn subscribe(&self, events: Sender<Event>) {
+    if let Some(control) = &self.control {
+      control.subscribers.lock().push(events);
+    }
+  }
+
+  fn as_tmux(&self) -> Option<&Tmux> {
+    Some(self)
+  }
+}
+
+/// A `tmux -C` client attached to a session, which runs commands over a single pipe and reports
+/// pane output as it happens.
+struct Control {
+  stdin: Mutex<ChildStdin>,
+  responses: Mutex<Receiver<Result<Vec<u8>>>>,
+  subscribers: Arc<Mutex<Vec<Sender<Event>>>>,
+  child: Mutex<Child>,
+}
+
+impl Control {
+  fn new(server: &Server, target: &str) -> Result<Self> {
+    let mut child = Command::new("tmux")
+      .arg("-S")
+      .arg(&server.socket)
+      .args(["-C", "attach-session", "-t", target])
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::null())
+      .spawn()
+      .context("tmux -C")?;
+
+    let stdin = child.stdin.take().ok_or(anyhow::anyhow!("no stdin"))?;
+    let stdout = child.stdout.take().ok_or(anyhow::anyhow!("no stdout"))?;
+
+    let (sender, receiver) = mpsc::channel();
+    let subscribers = Arc::new(Mutex::new(Vec::new()));
+
+    let notify = subscribers.clone();
+    thread::spawn(move || Self::read(BufReader::new(stdout), &sender, &notify));
+
+    Ok(Self {
+      stdin: Mutex::new(stdin),
+      responses: Mutex::new(receiver),
+      subscribers,
+      child: Mutex::new(child),
+    })
+  }
+
+  fn command(&self, command: &str, args: &[&str]) -> Result<Vec<u8>> {
+    let line = [command]
+      .iter()
+      .chain(args)
+      .map(|arg| quote(arg))
+      .collect::<Vec<_>>()
+      .join(" ");
+
+    // held until the response arrives, as responses are matched to commands by order
+    let mut stdin = self.stdin.lock();
+    writeln!(stdin, "{line}")?;
+    stdin.flush()?;
+
+    self
+      .responses
+      .lock()
+      .recv()
+      .map_err(|_| anyhow::anyhow!("tmux control mode exited"))?
+  }
+
+  /// Reads command output blocks and notifications until the client exits.
+  fn read(mut stdout: impl BufRead, responses: &Sender<Result<Vec<u8>>>, subscribers: &Mutex<Vec<Sender<Event>>>) {
+    let notify = || subscribers.lock().retain(|events| events.send(Event::Output).is_ok());
+
+    let mut line = Vec::new();
+    // the guard of the block being read, and whether it answers one of our commands
+    let mut block: Option<(Vec<u8>, bool)> = None;
+    let mut output = Vec::new();
+
+    while matches!(stdout.read_until(b'\n', &mut line), Ok(1..)) {
+      if line.ends_with(b"\n") {
+        line.pop();
+      }
+
+      match &mut block {
+        Some((guard, ours)) => {
+          if let Some(end) = line.strip_prefix(b"%end ").or(line.strip_prefix(b"%error ")) {
+            if end == guard.as_slice() {
+              if *ours {
+                let result = if line.starts_with(b"%end") {
+                  Ok(std::mem::take(&mut output))
+                } else {
+                  Err(anyhow::anyhow!("{}", String::from_utf8_lossy(&output).trim()))
+                };
+
+                let _ignore = responses.send(result);
+              }
+
+              output.clear();
+              block = None;
+              line.clear();
+              continue;
+            }
+          }
+
+          output.extend_from_slice(&line);
+          output.push(b'\n');
+        }
+
+        None => {
+          if let Some(guard) = line.strip_prefix(b"%begin ") {
+            // the flags are 1 for commands sent by this client
+            let ours = guard.ends_with(b" 1");
+            block = Some((guard.to_vec(), ours));
+          } else if line.starts_with(b"%exit") {
+            break;
+          } else if line.starts_with(b"%output")
+            || line.starts_with(b"%extended-output")
+            || line.starts_with(b"%layout-change")
+          {
+            notify();
+          }
+        }
+      }
+
+      line.clear();
+    }
+
+    // wakes anyone waiting on the session, which is now gone
+    notify();
+  }
+}
+
+impl Drop for Control {
+  fn drop(&mut self) {
+    let mut child = self.child.lock();
+
+    // only the client exits, the session is left running
+    let _ignore = child.kill();
+    let _ignore = child.wait();
   }
 }
 
-fn tmux_command<I, S>(command: &str, args: I) -> Result<Vec<u8>>
-where
-  I: IntoIterator<Item = S>,
-  S: AsRef<OsStr>,
-{
-  let output = Command::new("tmux")
-    .args(["-L", "kak-popup"])
-    .arg(command)
-    .args(args)
-    .output()
-    .with_context(|| format!("tmux {command}"))?;
-
-  if !output.status.success() {
-    return Err(anyhow::anyhow!(
-      "tmux {command} exited with non-zero status: {}, err: {}",
-      output.status,
-      String::from_utf8_lossy(&output.stderr),
-    ));
-  }
-
-  Ok(output.stdout)
+/// Quotes an argument for tmux's command parser.
+fn quote(arg: &str) -> String {
+  let mut quoted = String::from("\"");
+
+  for c in arg.chars() {
+    match c {
+      '"' | '\\' | '$' => {
+        quoted.push('\\');
+        quoted.push(c);
+      }
+      c if c.is_ascii_control() => quoted.push_str(&format!("\\{:03o}", c as u8)),
+      c => quoted.push(c),
+    }
+  }
+
+  quoted.push('"');
+  quoted
 }
@@ -0,0 +1,121 @@
+use std::{ops::Range, sync::Arc};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+
+use crate::geometry::{Point, Size};
+
+/// The padding while zoomed, which leaves room for the info box's border and kakoune's status line.
+const ZOOM_PADDING: usize = 4;
+
+/// How the terminal is shown, shared between the threads that read keys and draw the popup.
+#[derive(Clone)]
+pub struct View {
+  inner: Arc<Mutex<State>>,
+}
+
+struct State {
+  title: String,
+  /// How many lines back into the history the popup is scrolled, or `None` outside of scroll mode.
+  scroll: Option<usize>,
+  selection: Option<Selection>,
+
+  /// The size of the kakoune window the popup is shown in.
+  window: Size,
+  padding: usize,
+  zoomed: bool,
+}
+
+/// Cells selected by dragging the mouse, in screen coordinates, from where the drag started to where it
+/// currently is.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+  pub start: Point,
+  pub end: Point,
+  /// Whether the button is still held, so that moving the mouse extends the selection.
+  pub dragging: bool,
+}
+
+impl Selection {
+  /// The columns selected on row `y`. Rows before the last are selected to the end of the line, as
+  /// terminals select text.
+  pub fn columns(&self, y: usize) -> Range<usize> {
+    let (start, end) = if (self.start.y, self.start.x) <= (self.end.y, self.end.x) {
+      (self.start, self.end)
+    } else {
+      (self.end, self.start)
+    };
+
+    if y < start.y || y > end.y {
+      return 0..0;
+    }
+
+    let from = if y == start.y { start.x } else { 0 };
+    let to = if y == end.y { end.x + 1 } else { usize::MAX };
+
+    from..to
+  }
+}
+
+impl View {
+  pub fn new(title: Option<String>, window: Size, padding: usize) -> Self {
+    Self {
+      inner: Arc::new(Mutex::new(State {
+        title: title.unwrap_or_default(),
+        scroll: None,
+        selection: None,
+
+        window,
+        padding,
+        zoomed: false,
+      })),
+    }
+  }
+
+  pub fn title(&self) -> String {
+    self.inner.lock().title.clone()
+  }
+
+  pub fn scroll(&self) -> Option<usize> {
+    self.inner.lock().scroll
+  }
+
+  pub fn set_scroll(&self, scroll: Option<usize>) {
+    self.inner.lock().scroll = scroll;
+  }
+
+  pub fn selection(&self) -> Option<Selection> {
+    self.inner.lock().selection
+  }
+
+  pub fn set_selection(&self, selection: Option<Selection>) {
+    self.inner.lock().selection = selection;
+  }
+
+  /// The padding around the popup, which shrinks while zoomed.
+  pub fn padding(&self) -> usize {
+    let state = self.inner.lock();
+
+    if state.zoomed {
+      state.padding.min(ZOOM_PADDING)
+    } else {
+      state.padding
+    }
+  }
+
+  /// The size of the terminal, given the window it's shown in and the padding.
+  pub fn size(&self) -> Result<Size> {
+    let window = self.inner.lock().window;
+
+    window.padded(self.padding())
+  }
+
+  pub fn set_window(&self, window: Size) {
+    self.inner.lock().window = window;
+  }
+
+  pub fn toggle_zoom(&self) {
+    let mut state = self.inner.lock();
+    state.zoomed = !state.zoomed;
+  }
+}
@@ -0,0 +1,73 @@
+use anyhow::Result;
+
+use crate::{backend::Backend, buffer::Grid, escape, kakoune::Kakoune, view::Selection};
+
+/// How much of the terminal to yank.
+#[derive(Clone, Copy)]
+pub enum Extent {
+  /// The screen as it was `scroll` lines back into the history, as shown in scroll mode.
+  Screen { scroll: usize },
+  /// Everything that has scrolled off the screen, followed by the screen.
+  History,
+  /// The cells selected with the mouse on the screen `scroll` lines back.
+  Selection { scroll: usize, selection: Selection },
+}
+
+/// Where yanked text ends up in kakoune.
+pub enum Target {
+  /// A register, named as `set-register` names them.
+  Register(String),
+  /// A scratch buffer named after the popup's title, replacing any earlier yank into it.
+  Buffer,
+}
+
+/// Sends the text of `backend`, without any styling, to kakoune.
+pub fn yank(kakoune: &Kakoune, backend: &dyn Backend, title: &str, extent: Extent, target: &Target) -> Result<()> {
+  let text = match extent {
+    Extent::Screen { scroll } => Grid::new(&backend.capture_pane(scroll)?, None).plain_text(),
+    Extent::History => Grid::new(&backend.capture_history()?, None).plain_text(),
+    Extent::Selection { scroll, selection } => {
+      Grid::new(&backend.capture_pane(scroll)?, None).selected_text(&selection)
+    }
+  };
+
+  let lines = text.lines().count();
+  let text = escape::kak(text);
+
+  match target {
+    Target::Register(register) => kakoune.eval(format!(
+      "
+        set-register {register} {text}
+        echo yanked {lines} lines into register {register}
+      ",
+      register = escape::kak(register),
+    )),
+
+    Target::Buffer => {
+      let name = if title.is_empty() {
+        "*popup*".to_string()
+      } else {
+        format!("*popup {title}*")
+      };
+
+      let name = escape::kak(name);
+      let fill = format!(
+        "
+          set-register dquote {text}
+          evaluate-commands -draft {edit}
+          execute-keys -buffer {name} '%R'
+        ",
+        edit = escape::kak(format!("edit! -scratch {name}")),
+      );
+
+      // the buffer is filled from a draft context, leaving the client on whatever it was showing
+      kakoune.eval(format!(
+        "
+          evaluate-commands -save-regs '\"' {fill}
+          echo yanked {lines} lines into {name}
+        ",
+        fill = escape::kak(fill),
+      ))
+    }
+  }
+}